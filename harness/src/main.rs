@@ -0,0 +1,108 @@
+//! Runs a benchmark program built for `wasm32-unknown-unknown` on an embedded
+//! wasmtime, supplying the host interface of `rust/src/host.rs` instead of WASI.
+//!
+//! ```sh
+//! $ harness <module.wasm> [args...] < input > output
+//! ```
+
+use anyhow::{anyhow, Context};
+use std::io::{self, BufWriter, Read, StdinLock, StdoutLock, Write};
+use wasmtime::{Caller, Engine, Extern, Linker, Memory, Module, Store};
+
+struct Host {
+    args: Vec<String>,
+    stdin: StdinLock<'static>,
+    stdout: BufWriter<StdoutLock<'static>>,
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> anyhow::Result<Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => Err(anyhow!("module does not export its memory")),
+    }
+}
+
+fn guest_slice(data: &mut [u8], ptr: u32, len: u32) -> anyhow::Result<&mut [u8]> {
+    let (ptr, len) = (ptr as usize, len as usize);
+    data.get_mut(ptr..ptr + len)
+        .ok_or_else(|| anyhow!("out of bounds memory access"))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let path = args
+        .first()
+        .context("usage: harness <module.wasm> [args...]")?;
+
+    let engine = Engine::default();
+    let module = Module::from_file(&engine, path)?;
+    let mut linker = Linker::<Host>::new(&engine);
+
+    linker.func_wrap(
+        "bench",
+        "read_input",
+        |mut caller: Caller<'_, Host>, ptr: u32, len: u32| -> anyhow::Result<u32> {
+            let memory = memory(&mut caller)?;
+            let (data, host) = memory.data_and_store_mut(&mut caller);
+            let n = host.stdin.read(guest_slice(data, ptr, len)?)?;
+            Ok(n as u32)
+        },
+    )?;
+
+    linker.func_wrap(
+        "bench",
+        "write_output",
+        |mut caller: Caller<'_, Host>, ptr: u32, len: u32| -> anyhow::Result<()> {
+            let memory = memory(&mut caller)?;
+            let (data, host) = memory.data_and_store_mut(&mut caller);
+            host.stdout.write_all(guest_slice(data, ptr, len)?)?;
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        "bench",
+        "arg",
+        |mut caller: Caller<'_, Host>, index: u32, ptr: u32, len: u32| -> anyhow::Result<i32> {
+            let memory = memory(&mut caller)?;
+            let (data, host) = memory.data_and_store_mut(&mut caller);
+            let arg = match host.args.get(index as usize) {
+                Some(arg) => arg.as_bytes(),
+                None => return Ok(-1),
+            };
+            let n = arg.len().min(len as usize);
+            guest_slice(data, ptr, n as u32)?.copy_from_slice(&arg[..n]);
+            Ok(arg.len() as i32)
+        },
+    )?;
+
+    let host = Host {
+        args,
+        stdin: io::stdin().lock(),
+        stdout: BufWriter::new(io::stdout().lock()),
+    };
+    let mut store = Store::new(&engine, host);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    // Rust exports the C `main(argc, argv)` for binaries, the arguments are
+    // passed through the `arg` import instead.
+    let main = instance
+        .get_typed_func::<(i32, i32), i32>(&mut store, "main")
+        .or_else(|_| instance.get_typed_func::<(i32, i32), i32>(&mut store, "__main_argc_argv"));
+    let code = match main {
+        Ok(main) => main.call(&mut store, (0, 0))?,
+        Err(_) => {
+            instance
+                .get_typed_func::<(), ()>(&mut store, "_start")
+                .context("module exports no entry point")?
+                .call(&mut store, ())?;
+            0
+        }
+    };
+
+    store.data_mut().stdout.flush()?;
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
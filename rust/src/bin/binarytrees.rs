@@ -11,87 +11,395 @@
 
 extern crate bumpalo;
 // extern crate rayon;
+extern crate wasm_bench;
 
-use bumpalo::Bump;
+use wasm_bench::memory;
+use wasm_bench::println;
 // use rayon::prelude::*;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-struct Tree<'a> {
-    left: Option<&'a Tree<'a>>,
-    right: Option<&'a Tree<'a>>,
+#[cfg(feature = "memory-stats")]
+#[global_allocator]
+static ALLOCATOR: memory::Counting<std::alloc::System> = memory::Counting(std::alloc::System);
+
+/// How the nodes of the trees are allocated.
+trait Strategy {
+    /// Build a tree of `depth`, check it and free it.
+    fn check_tree(&mut self, depth: i32) -> i32;
+
+    /// Build a tree of `depth`, call `f` while it is alive and then check it.
+    fn with_long_lived_tree<R>(&mut self, depth: i32, f: impl FnOnce(&mut Self) -> R) -> (R, i32);
 }
 
-fn item_check(tree: &Tree) -> i32 {
-    if let (Some(left), Some(right)) = (tree.left, tree.right) {
-        1 + item_check(right) + item_check(left)
-    } else {
-        1
+/// Every tree in a `bumpalo::Bump` arena of its own, freed at once.
+mod arena {
+    use bumpalo::Bump;
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    struct Tree<'a> {
+        left: Option<&'a Tree<'a>>,
+        right: Option<&'a Tree<'a>>,
+    }
+
+    fn item_check(tree: &Tree) -> i32 {
+        if let (Some(left), Some(right)) = (tree.left, tree.right) {
+            1 + item_check(right) + item_check(left)
+        } else {
+            1
+        }
+    }
+
+    fn bottom_up_tree<'r>(arena: &'r Bump, depth: i32) -> &'r Tree<'r> {
+        let tree = arena.alloc(Tree {
+            left: None,
+            right: None,
+        });
+        if depth > 0 {
+            tree.right = Some(bottom_up_tree(arena, depth - 1));
+            tree.left = Some(bottom_up_tree(arena, depth - 1));
+        }
+        tree
+    }
+
+    pub struct Arena;
+
+    impl super::Strategy for Arena {
+        fn check_tree(&mut self, depth: i32) -> i32 {
+            let arena = Bump::new();
+            let a = bottom_up_tree(&arena, depth);
+            item_check(a)
+        }
+
+        fn with_long_lived_tree<R>(
+            &mut self,
+            depth: i32,
+            f: impl FnOnce(&mut Self) -> R,
+        ) -> (R, i32) {
+            let long_lived_arena = Bump::new();
+            let long_lived_tree = bottom_up_tree(&long_lived_arena, depth);
+            let r = f(self);
+            (r, item_check(long_lived_tree))
+        }
     }
 }
 
-fn bottom_up_tree<'r>(arena: &'r Bump, depth: i32) -> &'r Tree<'r> {
-    let tree = arena.alloc(Tree {
-        left: None,
-        right: None,
-    });
-    if depth > 0 {
-        tree.right = Some(bottom_up_tree(arena, depth - 1));
-        tree.left = Some(bottom_up_tree(arena, depth - 1));
+/// Every node in a `Box` of its own from the global allocator, which is
+/// dlmalloc in a wasm module.
+mod boxed {
+    struct Tree {
+        left: Option<Box<Tree>>,
+        right: Option<Box<Tree>>,
+    }
+
+    fn item_check(tree: &Tree) -> i32 {
+        if let (Some(left), Some(right)) = (&tree.left, &tree.right) {
+            1 + item_check(right) + item_check(left)
+        } else {
+            1
+        }
+    }
+
+    fn bottom_up_tree(depth: i32) -> Box<Tree> {
+        let mut tree = Box::new(Tree {
+            left: None,
+            right: None,
+        });
+        if depth > 0 {
+            tree.right = Some(bottom_up_tree(depth - 1));
+            tree.left = Some(bottom_up_tree(depth - 1));
+        }
+        tree
+    }
+
+    pub struct Boxed;
+
+    impl super::Strategy for Boxed {
+        fn check_tree(&mut self, depth: i32) -> i32 {
+            item_check(&bottom_up_tree(depth))
+        }
+
+        fn with_long_lived_tree<R>(
+            &mut self,
+            depth: i32,
+            f: impl FnOnce(&mut Self) -> R,
+        ) -> (R, i32) {
+            let long_lived_tree = bottom_up_tree(depth);
+            let r = f(self);
+            (r, item_check(&long_lived_tree))
+        }
+    }
+}
+
+/// The nodes of all trees in one growing `Vec`, referred to by index. Freed
+/// nodes are linked into a free list through `left` and reused first.
+mod pool {
+    const NIL: u32 = u32::MAX;
+
+    #[derive(Clone, Copy)]
+    struct Node {
+        left: u32,
+        right: u32,
+    }
+
+    pub struct Pool {
+        nodes: Vec<Node>,
+        free: u32,
+    }
+
+    impl Pool {
+        pub fn new() -> Pool {
+            Pool {
+                nodes: Vec::new(),
+                free: NIL,
+            }
+        }
+
+        fn alloc(&mut self) -> u32 {
+            let node = Node {
+                left: NIL,
+                right: NIL,
+            };
+            if self.free == NIL {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as u32
+            } else {
+                let index = self.free;
+                self.free = self.nodes[index as usize].left;
+                self.nodes[index as usize] = node;
+                index
+            }
+        }
+
+        fn free_tree(&mut self, tree: u32) {
+            let Node { left, right } = self.nodes[tree as usize];
+            if left != NIL {
+                self.free_tree(right);
+                self.free_tree(left);
+            }
+            self.nodes[tree as usize].left = self.free;
+            self.free = tree;
+        }
+
+        fn item_check(&self, tree: u32) -> i32 {
+            let Node { left, right } = self.nodes[tree as usize];
+            if left != NIL {
+                1 + self.item_check(right) + self.item_check(left)
+            } else {
+                1
+            }
+        }
+
+        fn bottom_up_tree(&mut self, depth: i32) -> u32 {
+            let tree = self.alloc();
+            if depth > 0 {
+                let right = self.bottom_up_tree(depth - 1);
+                let left = self.bottom_up_tree(depth - 1);
+                self.nodes[tree as usize] = Node { left, right };
+            }
+            tree
+        }
+    }
+
+    impl super::Strategy for Pool {
+        fn check_tree(&mut self, depth: i32) -> i32 {
+            let tree = self.bottom_up_tree(depth);
+            let check = self.item_check(tree);
+            self.free_tree(tree);
+            check
+        }
+
+        fn with_long_lived_tree<R>(
+            &mut self,
+            depth: i32,
+            f: impl FnOnce(&mut Self) -> R,
+        ) -> (R, i32) {
+            let long_lived_tree = self.bottom_up_tree(depth);
+            let r = f(self);
+            (r, self.item_check(long_lived_tree))
+        }
     }
-    tree
 }
 
-fn inner(depth: i32, iterations: i32) -> String {
+fn inner<S: Strategy>(strategy: &mut S, depth: i32, iterations: i32) -> String {
     let chk: i32 = (0..iterations)
         .into_iter()
-        .map(|_| {
-            let arena = Bump::new();
-            let a = bottom_up_tree(&arena, depth);
-            item_check(a)
-        })
+        .map(|_| strategy.check_tree(depth))
         .sum();
     format!("{}\t trees of depth {}\t check: {}", iterations, depth, chk)
 }
 
+enum Allocator {
+    Arena,
+    Box,
+    Pool,
+}
+
+struct Options {
+    n: i32,
+    allocator: Allocator,
+    memory_stats: bool,
+    reserve_mib: usize,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        n: 10,
+        allocator: Allocator::Arena,
+        memory_stats: false,
+        reserve_mib: 0,
+    };
+
+    let mut args = wasm_bench::host::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-a" | "--allocator" => {
+                opts.allocator = match args.next().as_deref() {
+                    Some("arena") => Allocator::Arena,
+                    Some("box") => Allocator::Box,
+                    Some("pool") => Allocator::Pool,
+                    a => {
+                        return Err(format!(
+                            "invalid allocator (arena, box or pool): {}",
+                            a.unwrap_or_default()
+                        ))
+                    }
+                }
+            }
+            "-m" | "--memory-stats" if cfg!(feature = "memory-stats") => opts.memory_stats = true,
+            "-m" | "--memory-stats" => {
+                return Err("--memory-stats needs a build with the memory-stats feature".to_string())
+            }
+            "-r" | "--reserve" => {
+                let arg = args.next().unwrap_or_default();
+                opts.reserve_mib = arg
+                    .parse()
+                    .map_err(|_| format!("invalid reserve (MiB): {}", arg))?;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ => {
+                opts.n = match arg.parse() {
+                    Ok(n) if n >= 0 => n,
+                    _ => return Err(format!("invalid depth: {}", arg)),
+                }
+            }
+        }
+    }
+    Ok(opts)
+}
+
 fn main() {
-    let n = std::env::args()
-        .nth(1)
-        .and_then(|n| n.parse().ok())
-        .unwrap_or(10);
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Grow the memory once up front. In a wasm module the freed block stays
+    // with the allocator, so the trees are built without `memory.grow`.
+    if opts.reserve_mib > 0 {
+        drop(Vec::<u8>::with_capacity(opts.reserve_mib << 20));
+    }
+    // only the growth while building the trees
+    if opts.memory_stats {
+        memory::enable();
+    }
+
+    let n = opts.n;
+    let print = |line: String| println!("{}", line);
+    match opts.allocator {
+        Allocator::Arena => run(arena::Arena, n, print),
+        Allocator::Box => run(boxed::Boxed, n, print),
+        Allocator::Pool => run(pool::Pool::new(), n, print),
+    }
+
+    // on stderr, to keep the output the same
+    if opts.memory_stats {
+        let stats = memory::stats();
+        eprintln!(
+            "memory grows={} grown_pages={} ({} MiB) pages={} ({} MiB)",
+            stats.grows,
+            stats.grown_pages,
+            (stats.grown_pages * memory::PAGE_SIZE) >> 20,
+            stats.pages,
+            (stats.pages * memory::PAGE_SIZE) >> 20
+        );
+    }
+}
+
+/// Run the benchmark, passing every line of its output to `out`.
+fn run<S: Strategy>(mut strategy: S, n: i32, mut out: impl FnMut(String)) {
     let min_depth = 4;
     let max_depth = if min_depth + 2 > n { min_depth + 2 } else { n };
 
     {
-        let arena = Bump::new();
         let depth = max_depth + 1;
-        let tree = bottom_up_tree(&arena, depth);
-        println!(
+        out(format!(
             "stretch tree of depth {}\t check: {}",
             depth,
-            item_check(tree)
-        );
+            strategy.check_tree(depth)
+        ));
     }
 
-    let long_lived_arena = Bump::new();
-    let long_lived_tree = bottom_up_tree(&long_lived_arena, max_depth);
-
-    let messages = (min_depth / 2..=max_depth / 2)
-        .into_iter()
-        .map(|half_depth| {
-            let depth = half_depth * 2;
-            let iterations = 1 << ((max_depth - depth + min_depth) as u32);
-            let res = inner(depth, iterations);
-            res
-        })
-        .collect::<Vec<_>>();
+    let (messages, long_lived_check) = strategy.with_long_lived_tree(max_depth, |strategy| {
+        (min_depth / 2..=max_depth / 2)
+            .into_iter()
+            .map(|half_depth| {
+                let depth = half_depth * 2;
+                let iterations = 1 << ((max_depth - depth + min_depth) as u32);
+                let res = inner(strategy, depth, iterations);
+                res
+            })
+            .collect::<Vec<_>>()
+    });
 
     for message in messages {
-        println!("{}", message);
+        out(message);
     }
 
-    println!(
+    out(format!(
         "long lived tree of depth {}\t check: {}",
-        max_depth,
-        item_check(long_lived_tree)
-    );
+        max_depth, long_lived_check
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output<S: Strategy>(strategy: S, n: i32) -> Vec<String> {
+        let mut lines = vec![];
+        run(strategy, n, |line| lines.push(line));
+        lines
+    }
+
+    #[test]
+    fn strategies_check_the_same() {
+        for n in [0, 6, 10] {
+            let arena = output(arena::Arena, n);
+            assert_eq!(output(boxed::Boxed, n), arena, "n={}", n);
+            assert_eq!(output(pool::Pool::new(), n), arena, "n={}", n);
+        }
+        assert_eq!(
+            output(pool::Pool::new(), 6),
+            [
+                "stretch tree of depth 7\t check: 255",
+                "64\t trees of depth 4\t check: 1984",
+                "16\t trees of depth 6\t check: 2032",
+                "long lived tree of depth 6\t check: 127",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_node_is_checked() {
+        let mut pool = pool::Pool::new();
+        for depth in 0..12 {
+            let nodes = (1 << (depth + 1)) - 1;
+            assert_eq!(arena::Arena.check_tree(depth), nodes);
+            assert_eq!(boxed::Boxed.check_tree(depth), nodes);
+            // reusing the nodes freed by the trees before
+            assert_eq!(pool.check_tree(depth), nodes);
+        }
+    }
 }
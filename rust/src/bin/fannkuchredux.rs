@@ -9,9 +9,15 @@
 // Mazurov.
 
 // extern crate rayon;
+extern crate wasm_bench;
 
 // use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
 use std::mem::replace;
+use std::str::FromStr;
+use wasm_bench::println;
 
 // This value controls how many blocks the workload is broken up into (as long
 // as the value is less than or equal to the factorial of the argument to this
@@ -20,147 +26,467 @@ use std::mem::replace;
 // divides evenly into all factorials larger than it. It should also be around
 // 2-8 times the amount of threads you want to use in order to create enough
 // blocks to more evenly distribute the workload amongst the threads.
-const PREFERRED_NUMBER_OF_BLOCKS_TO_USE: usize = 12;
+const PREFERRED_NUMBER_OF_BLOCKS_TO_USE: u64 = 12;
 
-// One greater than the maximum `n` value. Used to size stack arrays.
-const MAX_N: usize = 16;
+// One greater than the maximum `n` value, 21! does not fit in 64 bits.
+// Used to size the factorial table.
+const MAX_N: usize = 21;
 
-fn main() {
-    let n = std::env::args().nth(1).unwrap().parse().unwrap();
+// Permutations are kept in stack arrays of `N` elements, `N` a power of two
+// greater than `n` so that indices masked with `N - 1` need no bounds checks:
+// `SMALL_N` up to 15, `LARGE_N` above.
+const SMALL_N: usize = 16;
+const LARGE_N: usize = 32;
 
-    // This assert eliminates several bounds checks.
-    assert!(n < MAX_N);
+struct Options {
+    n: usize,
+    block_count: Option<u64>,
+    blocks: Option<(u64, u64)>,
+    merge: bool,
+}
 
-    // Create and initialize factorial_lookup_table.
-    let factorial_lookup_table = {
-        let mut table: [usize; MAX_N] = [0; MAX_N];
-        table[0] = 1;
-        for i in 1..MAX_N {
-            table[i] = i * table[i - 1];
-        }
-        table
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        n: 7,
+        block_count: None,
+        blocks: None,
+        merge: false,
     };
 
-    // Determine the block_size to use. If n! is less than
-    // PREFERRED_NUMBER_OF_BLOCKS_TO_USE then just use a single block to prevent
-    // block_size from being set to 0. This also causes smaller values of n to
-    // be computed serially which is faster and uses less resources for small
-    // values of n.
-    let block_size = 1.max(factorial_lookup_table[n] / PREFERRED_NUMBER_OF_BLOCKS_TO_USE);
-    let block_count = factorial_lookup_table[n] / block_size;
-
-    // Iterate over each block.
-    let (checksum, max_flip_count) = (0..block_count)
-        .into_iter()
-        .map(|bn| {
-            let initial_permutation_index = bn * block_size;
-
-            let mut count: [usize; MAX_N] = [0; MAX_N];
-            let mut current_permutation: [u8; MAX_N] =
-                [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
-
-            // Initialize count and current_permutation.
-            {
-                let mut temp_permutation: [u8; MAX_N] = [0; MAX_N];
-                let mut permutation_index = initial_permutation_index;
-                for i in (1..n).rev() {
-                    let f = factorial_lookup_table[i];
-                    let d = permutation_index / f;
-
-                    count[i] = d;
-
-                    // Rotate the permutation left by d places. This is faster
-                    // than using slice::rotate_left.
-                    temp_permutation[0..=i - d].copy_from_slice(&current_permutation[d..=i]);
-                    temp_permutation[i - d + 1..=i].copy_from_slice(&current_permutation[..d]);
-                    current_permutation = temp_permutation;
-
-                    permutation_index = permutation_index % f;
+    let mut args = wasm_bench::host::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--block-count" => {
+                let arg = args.next().unwrap_or_default();
+                opts.block_count = match arg.parse() {
+                    Ok(c) if c > 0 => Some(c),
+                    _ => return Err(format!("invalid block count: {}", arg)),
+                };
+            }
+            "-b" | "--blocks" => {
+                let arg = args.next().unwrap_or_default();
+                let range = arg
+                    .split_once("..")
+                    .and_then(|(a, b)| Some((a.parse().ok()?, b.parse().ok()?)));
+                opts.blocks = match range {
+                    Some((a, b)) if a < b => Some((a, b)),
+                    _ => return Err(format!("invalid block range (A..B): {}", arg)),
+                };
+            }
+            "-m" | "--merge" => opts.merge = true,
+            _ => {
+                opts.n = match arg.parse() {
+                    Ok(n) if (1..MAX_N).contains(&n) => n,
+                    _ => return Err(format!("invalid n (1 to {}): {}", MAX_N - 1, arg)),
                 }
             }
+        }
+    }
+    Ok(opts)
+}
 
-            let mut max_flip_count = 0;
-            let mut checksum = 0;
-
-            // Iterate over each permutation in the block.
-            let last_permutation_index = initial_permutation_index + block_size;
-            for permutation_index in initial_permutation_index..last_permutation_index {
-                // If the first value in the current_permutation is not 1 (0)
-                // then we will need to do at least one flip for the
-                // current_permutation.
-                if current_permutation[0] > 0 {
-                    // Make a copy of current_permutation[] to work on.
-                    let mut temp_permutation = current_permutation;
-
-                    let mut flip_count: usize = 1;
-
-                    // Flip temp_permutation until the element at the
-                    // first_value index is 1 (0).
-                    let mut first_value = current_permutation[0] as usize & 0xF;
-                    while temp_permutation[first_value] > 0 {
-                        // Record the new_first_value and restore the old
-                        // first_value at its new flipped position.
-                        let new_first_value =
-                            replace(&mut temp_permutation[first_value], first_value as u8);
-
-                        // If first_value is greater than 3 (2) then we are
-                        // flipping a series of four or more values so we will
-                        // also need to flip additional elements in the middle
-                        // of the temp_permutation.
-                        if first_value > 2 {
-                            for (low_index, high_index) in
-                                (1..first_value).zip((1..first_value).rev())
-                            {
-                                temp_permutation.swap(high_index, low_index);
-
-                                if low_index + 3 > high_index {
-                                    break;
-                                }
-                            }
-                        }
+fn main() {
+    if let Err(e) = parse_args().and_then(run) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
 
-                        // Update first_value to new_first_value that we
-                        // recorded earlier.
-                        first_value = new_first_value as usize & 0xF;
-                        flip_count += 1;
-                    }
+fn run(opts: Options) -> Result<(), String> {
+    if opts.merge {
+        return merge();
+    }
+    let blocks = Blocks::new(opts.n, opts.block_count)?;
 
-                    // Update the checksum.
-                    if permutation_index % 2 == 0 {
-                        checksum += flip_count;
-                    } else {
-                        checksum -= flip_count;
-                    }
+    match opts.blocks {
+        None => {
+            // Iterate over each block.
+            let (checksum, max_flip_count) = total((0..blocks.count).map(|bn| blocks.compute(bn)));
 
-                    // Update max_flip_count if necessary.
-                    max_flip_count = max_flip_count.max(flip_count);
-                }
+            // Output the results to stdout.
+            println!("{}", checksum);
+            println!("Pfannkuchen({}) = {}", opts.n, max_flip_count);
+        }
+        Some((a, b)) => {
+            if b > blocks.count {
+                return Err(format!("blocks {}..{} out of {}", a, b, blocks.count));
+            }
+            // one line per block as soon as it is done, so that an interrupted
+            // run only has to redo the missing blocks
+            for bn in a..b {
+                println!("{}", blocks.compute(bn));
+            }
+        }
+    }
+    Ok(())
+}
 
-                // Generate the next permutation.
-                current_permutation.swap(0, 1);
-                let mut first_value = current_permutation[0];
-                for i in 1..MAX_N - 2 {
-                    count[i] += 1;
-                    if count[i] <= i {
-                        break;
-                    }
-                    count[i] = 0;
+/// The n! permutations split into `count` blocks of `size` permutations.
+struct Blocks {
+    n: usize,
+    size: u64,
+    count: u64,
+    factorial_lookup_table: [u64; MAX_N],
+}
 
-                    let new_first_value = current_permutation[1];
+impl Blocks {
+    /// `count` blocks if given, which must divide n!.
+    fn new(n: usize, count: Option<u64>) -> Result<Blocks, String> {
+        // Create and initialize factorial_lookup_table.
+        let factorial_lookup_table = {
+            let mut table: [u64; MAX_N] = [0; MAX_N];
+            table[0] = 1;
+            for i in 1..MAX_N {
+                table[i] = i as u64 * table[i - 1];
+            }
+            table
+        };
 
-                    for j in 0..i + 1 {
-                        current_permutation[j] = current_permutation[j + 1];
-                    }
+        // Determine the block_size to use. If n! is less than
+        // PREFERRED_NUMBER_OF_BLOCKS_TO_USE then just use a single block to prevent
+        // block_size from being set to 0. This also causes smaller values of n to
+        // be computed serially which is faster and uses less resources for small
+        // values of n.
+        let size = match count {
+            None => 1.max(factorial_lookup_table[n] / PREFERRED_NUMBER_OF_BLOCKS_TO_USE),
+            Some(c) if factorial_lookup_table[n] % c == 0 => factorial_lookup_table[n] / c,
+            Some(c) => return Err(format!("{}! is not a multiple of the block count {}", n, c)),
+        };
+        Ok(Blocks {
+            n,
+            size,
+            count: factorial_lookup_table[n] / size,
+            factorial_lookup_table,
+        })
+    }
+
+    /// The result of block `bn`.
+    #[inline(always)]
+    fn compute(&self, bn: u64) -> Partial {
+        let (n, start) = (self.n, bn * self.size);
+        let (checksum, max_flip_count) = if n < SMALL_N {
+            block::<SMALL_N>(n, start, self.size, &self.factorial_lookup_table)
+        } else {
+            block::<LARGE_N>(n, start, self.size, &self.factorial_lookup_table)
+        };
+        Partial {
+            n,
+            block_count: self.count,
+            block: bn,
+            checksum,
+            max_flip_count,
+        }
+    }
+}
+
+/// Checksum and maximum flip count of the blocks together.
+fn total(partials: impl Iterator<Item = Partial>) -> (i64, usize) {
+    partials.fold((0, 0), |(cs, mf), p| {
+        (cs + p.checksum, mf.max(p.max_flip_count))
+    })
+}
+
+/// Checksum and maximum flip count of the `block_size` permutations starting
+/// at `initial_permutation_index`.
+#[inline(always)]
+fn block<const N: usize>(
+    n: usize,
+    initial_permutation_index: u64,
+    block_size: u64,
+    factorial_lookup_table: &[u64; MAX_N],
+) -> (i64, usize) {
+    let mut count: [usize; N] = [0; N];
+    let mut current_permutation: [u8; N] = [0; N];
+    for (i, p) in current_permutation.iter_mut().enumerate() {
+        *p = i as u8;
+    }
+
+    // Initialize count and current_permutation.
+    {
+        let mut temp_permutation: [u8; N] = [0; N];
+        let mut permutation_index = initial_permutation_index;
+        for i in (1..n).rev() {
+            let f = factorial_lookup_table[i];
+            let d = (permutation_index / f) as usize;
+
+            count[i] = d;
+
+            // Rotate the permutation left by d places. This is faster
+            // than using slice::rotate_left.
+            temp_permutation[0..=i - d].copy_from_slice(&current_permutation[d..=i]);
+            temp_permutation[i - d + 1..=i].copy_from_slice(&current_permutation[..d]);
+            current_permutation = temp_permutation;
+
+            permutation_index %= f;
+        }
+    }
+
+    let mut max_flip_count = 0;
+    let mut checksum = 0;
+
+    // Iterate over each permutation in the block.
+    let last_permutation_index = initial_permutation_index + block_size;
+    for permutation_index in initial_permutation_index..last_permutation_index {
+        // If the first value in the current_permutation is not 1 (0)
+        // then we will need to do at least one flip for the
+        // current_permutation.
+        if current_permutation[0] > 0 {
+            // Make a copy of current_permutation[] to work on.
+            let mut temp_permutation = current_permutation;
+
+            let mut flip_count: usize = 1;
+
+            // Flip temp_permutation until the element at the
+            // first_value index is 1 (0).
+            let mut first_value = current_permutation[0] as usize & (N - 1);
+            while temp_permutation[first_value] > 0 {
+                // Record the new_first_value and restore the old
+                // first_value at its new flipped position.
+                let new_first_value =
+                    replace(&mut temp_permutation[first_value], first_value as u8);
+
+                // If first_value is greater than 3 (2) then we are
+                // flipping a series of four or more values so we will
+                // also need to flip additional elements in the middle
+                // of the temp_permutation.
+                if first_value > 2 {
+                    for (low_index, high_index) in (1..first_value).zip((1..first_value).rev()) {
+                        temp_permutation.swap(high_index, low_index);
 
-                    current_permutation[i + 1] = first_value;
-                    first_value = new_first_value;
+                        if low_index + 3 > high_index {
+                            break;
+                        }
+                    }
                 }
+
+                // Update first_value to new_first_value that we
+                // recorded earlier.
+                first_value = new_first_value as usize & (N - 1);
+                flip_count += 1;
+            }
+
+            // Update the checksum.
+            if permutation_index % 2 == 0 {
+                checksum += flip_count as i64;
+            } else {
+                checksum -= flip_count as i64;
             }
-            (checksum, max_flip_count)
+
+            // Update max_flip_count if necessary.
+            max_flip_count = max_flip_count.max(flip_count);
+        }
+
+        // Generate the next permutation.
+        current_permutation.swap(0, 1);
+        let mut first_value = current_permutation[0];
+        for i in 1..N - 2 {
+            count[i] += 1;
+            if count[i] <= i {
+                break;
+            }
+            count[i] = 0;
+
+            let new_first_value = current_permutation[1];
+
+            for j in 0..i + 1 {
+                current_permutation[j] = current_permutation[j + 1];
+            }
+
+            current_permutation[i + 1] = first_value;
+            first_value = new_first_value;
+        }
+    }
+    (checksum, max_flip_count)
+}
+
+/// The result of one block, as printed with `--blocks` and read by `--merge`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Partial {
+    n: usize,
+    block_count: u64,
+    block: u64,
+    checksum: i64,
+    max_flip_count: usize,
+}
+
+impl fmt::Display for Partial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "n={} blocks={} block={} checksum={} max_flips={}",
+            self.n, self.block_count, self.block, self.checksum, self.max_flip_count
+        )
+    }
+}
+
+impl FromStr for Partial {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Partial, String> {
+        let mut fields = line.split_whitespace().map(|w| w.split_once('='));
+        let mut field = |name: &str| match fields.next() {
+            Some(Some((key, value))) if key == name => Ok(value),
+            _ => Err(format!("expected {}=: {}", name, line)),
+        };
+        let invalid = |_| format!("malformed partial result: {}", line);
+        Ok(Partial {
+            n: field("n")?.parse().map_err(invalid)?,
+            block_count: field("blocks")?.parse().map_err(invalid)?,
+            block: field("block")?.parse().map_err(invalid)?,
+            checksum: field("checksum")?.parse().map_err(invalid)?,
+            max_flip_count: field("max_flips")?.parse().map_err(invalid)?,
         })
-        .fold((0, 0), |(cs1, mf1), (cs2, mf2)| (cs1 + cs2, mf1.max(mf2)));
+    }
+}
 
-    // Output the results to stdout.
+/// Combine the partial results on stdin into the result of the whole run.
+fn merge() -> Result<(), String> {
+    let mut input = String::new();
+    wasm_bench::host::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| e.to_string())?;
+
+    let (n, checksum, max_flip_count) = merge_lines(&input)?;
     println!("{}", checksum);
     println!("Pfannkuchen({}) = {}", n, max_flip_count);
+    Ok(())
+}
+
+/// n, checksum and maximum flip count of the partial results in `input`.
+/// Every block must be there; a block may be there more than once, e.g.
+/// from several runtimes, but then with the same result.
+fn merge_lines(input: &str) -> Result<(usize, i64, usize), String> {
+    let mut partials = BTreeMap::<u64, Partial>::new();
+    for line in input.lines().filter(|l| !l.trim().is_empty()) {
+        let p = line.parse::<Partial>()?;
+        if let Some(q) = partials.values().next() {
+            if (p.n, p.block_count) != (q.n, q.block_count) {
+                return Err(format!("{} does not belong with {}", p, q));
+            }
+        }
+        if p.block >= p.block_count {
+            return Err(format!("no such block: {}", p));
+        }
+        match partials.get(&p.block) {
+            Some(q) if *q != p => {
+                return Err(format!("block {} differs: {} and {}", p.block, q, p))
+            }
+            _ => partials.insert(p.block, p),
+        };
+    }
+
+    let first = *partials.values().next().ok_or("no partial results")?;
+    let missing = (0..first.block_count)
+        .filter(|b| !partials.contains_key(b))
+        .map(|b| b.to_string())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(format!("missing blocks: {}", missing.join(" ")));
+    }
+
+    let (checksum, max_flip_count) = total(partials.into_values());
+    Ok((first.n, checksum, max_flip_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_run(n: usize) -> (i64, usize) {
+        let blocks = Blocks::new(n, None).unwrap();
+        total((0..blocks.count).map(|bn| blocks.compute(bn)))
+    }
+
+    /// The lines of `--blocks a..b` for every range of `ranges`.
+    fn lines(n: usize, count: Option<u64>, ranges: &[(u64, u64)]) -> String {
+        let blocks = Blocks::new(n, count).unwrap();
+        ranges
+            .iter()
+            .flat_map(|&(a, b)| a..b)
+            .map(|bn| format!("{}\n", blocks.compute(bn)))
+            .collect()
+    }
+
+    #[test]
+    fn partial_round_trip() {
+        let p = Partial {
+            n: 12,
+            block_count: 12,
+            block: 3,
+            checksum: -1234,
+            max_flip_count: 65,
+        };
+        let line = p.to_string();
+        assert_eq!(line, "n=12 blocks=12 block=3 checksum=-1234 max_flips=65");
+        assert_eq!(line.parse::<Partial>(), Ok(p));
+
+        let blocks = Blocks::new(7, Some(7)).unwrap();
+        for bn in 0..blocks.count {
+            let p = blocks.compute(bn);
+            assert_eq!(p.to_string().parse::<Partial>(), Ok(p));
+        }
+    }
+
+    #[test]
+    fn malformed_partials() {
+        for line in [
+            "",
+            "n=7 blocks=12 block=3 checksum=1",
+            "n=7 block=3 blocks=12 checksum=1 max_flips=2",
+            "n=7 blocks=12 block=x checksum=1 max_flips=2",
+            "n=7 blocks=12 block=-1 checksum=1 max_flips=2",
+        ] {
+            assert!(line.parse::<Partial>().is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn single_run_of_small_n() {
+        assert_eq!(single_run(7), (228, 16));
+        assert_eq!(single_run(1), (0, 0));
+    }
+
+    #[test]
+    fn merged_blocks_match_single_run() {
+        for n in [3, 5, 7] {
+            let (checksum, max_flip_count) = single_run(n);
+            for count in [None, Some(1), Some(2), Some(6)] {
+                let c = Blocks::new(n, count).unwrap().count;
+                for ranges in [
+                    vec![(0, c)],
+                    vec![(0, c / 2), (c / 2, c)],
+                    vec![(c - 1, c), (0, c)],
+                ] {
+                    let input = lines(n, count, &ranges);
+                    assert_eq!(
+                        merge_lines(&input),
+                        Ok((n, checksum, max_flip_count)),
+                        "n={} {:?} {:?}",
+                        n,
+                        count,
+                        ranges
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_rejects_incomplete_and_conflicting_blocks() {
+        let input = lines(7, Some(7), &[(0, 3), (4, 7)]);
+        assert_eq!(merge_lines(&input), Err("missing blocks: 3".to_string()));
+
+        let input = lines(7, Some(7), &[(0, 7)]) + "n=7 blocks=7 block=2 checksum=0 max_flips=0\n";
+        assert!(merge_lines(&input)
+            .unwrap_err()
+            .starts_with("block 2 differs"));
+
+        let input = lines(7, Some(7), &[(0, 7)]) + &lines(7, Some(5), &[(0, 1)]);
+        assert!(merge_lines(&input)
+            .unwrap_err()
+            .contains("does not belong with"));
+
+        assert!(merge_lines("n=7 blocks=7 block=7 checksum=0 max_flips=0").is_err());
+        assert_eq!(merge_lines("\n"), Err("no partial results".to_string()));
+    }
 }
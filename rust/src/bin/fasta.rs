@@ -7,12 +7,15 @@
 // contributed by Ryohei Machida
 
 extern crate core;
-extern crate spin;
+extern crate flate2;
+extern crate wasm_bench;
 
-use spin::Mutex;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use std::cmp;
 use std::io::{self, ErrorKind, Write};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
 const LINE_LENGTH: usize = 60;
 const IM: u32 = 139968;
@@ -130,27 +133,44 @@ impl MyRandom {
     }
 }
 
-struct MyStdOut {
+struct MyWriter<'a, W: Write> {
     thread_count: u16,
     next_thread_id: u16,
-    stdout: io::Stdout,
+    out: &'a mut W,
+    error: Option<io::Error>,
 }
 
-impl MyStdOut {
-    fn new(thread_count: u16) -> MyStdOut {
-        MyStdOut {
+impl<'a, W: Write> MyWriter<'a, W> {
+    fn new(out: &'a mut W, thread_count: u16) -> MyWriter<'a, W> {
+        MyWriter {
             thread_count,
             next_thread_id: 0,
-            stdout: io::stdout(),
+            out,
+            error: None,
         }
     }
 
-    fn write(&mut self, data: &[u8], cur_thread: u16) -> io::Result<()> {
+    // An I/O error is kept until all threads are done instead of being
+    // returned right away, so that no thread waits forever for the turn of a
+    // thread which already gave up.
+    fn write(&mut self, data: &[u8], cur_thread: u16) -> Result<(), ()> {
         if self.next_thread_id != cur_thread {
-            return Err(io::Error::new(ErrorKind::Other, ""));
+            return Err(());
         }
         self.next_thread_id = (self.next_thread_id + 1) % self.thread_count;
-        self.stdout.write_all(data)
+        if self.error.is_none() {
+            if let Err(e) = self.out.write_all(data) {
+                self.error = Some(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 }
 
@@ -162,7 +182,7 @@ fn gcd(a: usize, b: usize) -> usize {
     }
 }
 
-fn fasta_repeat(seq: &[u8], n: usize) -> io::Result<()> {
+fn fasta_repeat<W: Write>(out: &mut W, seq: &[u8], n: usize) -> io::Result<()> {
     let num_lines_per_buf = seq.len() / gcd(seq.len(), LINE_LENGTH);
     let buf_size = num_lines_per_buf * (LINE_LENGTH + 1);
     let mut buf = vec![0u8; buf_size];
@@ -177,11 +197,9 @@ fn fasta_repeat(seq: &[u8], n: usize) -> io::Result<()> {
         buf[i * (LINE_LENGTH + 1) + LINE_LENGTH] = b'\n';
     }
 
-    // write to stdout
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
+    // write to output
     while n2 >= buf_size {
-        stdout.write_all(buf.as_slice())?;
+        out.write_all(buf.as_slice())?;
         n2 -= buf_size;
     }
 
@@ -191,24 +209,40 @@ fn fasta_repeat(seq: &[u8], n: usize) -> io::Result<()> {
         n2 += 1;
     }
 
-    stdout.write_all(&buf[..n2])?;
+    out.write_all(&buf[..n2])?;
     Ok(())
 }
 
-fn fasta_random(
+/// A value which the threads take turns on, with the condition variable
+/// they wait on for their turn.
+type Shared<T> = (Mutex<T>, Condvar);
+
+/// Call `f` with the value of `shared` until it succeeds, which it does on
+/// the turn of the calling thread, sleeping until another thread is done in
+/// between.
+fn in_turn<T, R>(shared: &Shared<T>, mut f: impl FnMut(&mut T) -> Result<R, ()>) -> R {
+    let (lock, turn) = shared;
+    let mut value = lock.lock().unwrap();
+    loop {
+        if let Ok(r) = f(&mut value) {
+            drop(value);
+            turn.notify_all();
+            return r;
+        }
+        value = turn.wait(value).unwrap();
+    }
+}
+
+fn fasta_random<W: Write>(
     thread_id: u16,
-    rng: Arc<Mutex<MyRandom>>,
-    writer: Arc<Mutex<MyStdOut>>,
+    rng: Arc<Shared<MyRandom>>,
+    writer: &Shared<MyWriter<W>>,
     wr: WeightedRandom<u8>,
 ) {
     let mut rng_buf = [0u32; BLKLEN];
     let mut out_buf = [0u8; BLKLEN + LINES];
     loop {
-        let count = loop {
-            if let Ok(x) = rng.lock().gen(&mut rng_buf, thread_id) {
-                break x;
-            }
-        };
+        let count = in_turn(&rng, |rng| rng.gen(&mut rng_buf, thread_id));
 
         if count == 0 {
             break;
@@ -229,31 +263,36 @@ fn fasta_random(
             line_count += 1;
         }
 
-        while let Err(_) = writer
-            .lock()
-            .write(&out_buf[..(rng_buf.len() + line_count)], thread_id)
-        {}
+        let data = &out_buf[..(rng_buf.len() + line_count)];
+        in_turn(writer, |writer| writer.write(data, thread_id));
     }
 }
 
-fn fasta_random_par(
-    rng: Arc<Mutex<MyRandom>>,
+fn fasta_random_par<W: Write + Send>(
+    out: &mut W,
+    rng: Arc<Shared<MyRandom>>,
     wr: WeightedRandom<u8>,
     num_threads: u16,
 ) -> io::Result<()> {
-    let stdout = Arc::new(Mutex::new(MyStdOut::new(num_threads)));
-    fasta_random(0, rng, stdout, wr);
-    Ok(())
-}
+    let writer = (Mutex::new(MyWriter::new(out, num_threads)), Condvar::new());
+
+    // Blocks are handed out to the threads in turn by `MyRandom` and written
+    // in the same order by `MyWriter`, so the output does not depend on
+    // `num_threads`.
+    thread::scope(|scope| {
+        for thread_id in 1..num_threads {
+            let rng = rng.clone();
+            let wr = wr.clone();
+            let writer = &writer;
+            scope.spawn(move || fasta_random(thread_id, rng, writer, wr));
+        }
+        fasta_random(0, rng, &writer, wr);
+    });
 
-fn main() {
-    let n = std::env::args_os()
-        .nth(1)
-        .and_then(|s| s.into_string().ok())
-        .and_then(|n| n.parse().ok())
-        .unwrap_or(1000);
-    let num_threads: u16 = 1;
+    writer.0.into_inner().unwrap().finish()
+}
 
+fn fasta<W: Write + Send>(out: &mut W, n: usize, num_threads: u16) -> io::Result<()> {
     // Homo sapiens alu
     {
         let alu: [u8; 287] = *b"GGCCGGGCGCGGTGGCTCACGCCTGTAATCCCAGCACTTT\
@@ -265,11 +304,17 @@ fn main() {
                                 CCACTGCACTCCAGCCTGGGCGACAGAGCGAGACTCCGTC\
                                 TCAAAAA";
 
-        println!(">ONE Homo sapiens alu");
-        fasta_repeat(&alu, n * 2).unwrap();
+        writeln!(out, ">ONE Homo sapiens alu")?;
+        fasta_repeat(out, &alu, n * 2)?;
     }
 
-    let rng = Arc::new(Mutex::new(MyRandom::new(n * 3, num_threads)));
+    // no more threads than blocks of the longer random sequence
+    let blocks = cmp::max(1, (n * 5).div_ceil(BLKLEN));
+    let num_threads = cmp::min(num_threads as usize, blocks) as u16;
+    let rng = Arc::new((
+        Mutex::new(MyRandom::new(n * 3, num_threads)),
+        Condvar::new(),
+    ));
 
     // IUB ambiguity codes
     {
@@ -291,11 +336,11 @@ fn main() {
             (b'Y', 0.02),
         ]);
 
-        println!(">TWO IUB ambiguity codes");
-        fasta_random_par(rng.clone(), iub, num_threads).unwrap();
+        writeln!(out, ">TWO IUB ambiguity codes")?;
+        fasta_random_par(out, rng.clone(), iub, num_threads)?;
     }
 
-    rng.lock().reset(n * 5);
+    rng.0.lock().unwrap().reset(n * 5);
 
     // Homo sapience frequency
     {
@@ -306,7 +351,66 @@ fn main() {
             (b't', 0.3015094502008),
         ]);
 
-        println!(">THREE Homo sapiens frequency");
-        fasta_random_par(rng, homosapiens, num_threads).unwrap();
+        writeln!(out, ">THREE Homo sapiens frequency")?;
+        fasta_random_par(out, rng, homosapiens, num_threads)?;
+    }
+
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let mut n = 1000;
+    let mut gzip = false;
+    let mut num_threads: u16 = 1;
+
+    let invalid = |what: &str, arg: &str| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid {}: {}", what, arg),
+        )
+    };
+
+    let mut args = wasm_bench::host::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-z" | "--gzip" => gzip = true,
+            "-t" | "--threads" => {
+                let arg = args.next().unwrap_or_default();
+                num_threads = match arg.parse() {
+                    Ok(t) if t > 0 => t,
+                    _ => return Err(invalid("thread count", &arg)),
+                };
+            }
+            _ => n = arg.parse().map_err(|_| invalid("length", &arg))?,
+        }
+    }
+
+    let mut stdout = wasm_bench::host::stdout();
+    if gzip {
+        let mut out = GzEncoder::new(stdout, Compression::default());
+        fasta(&mut out, n, num_threads)?;
+        out.finish()?.flush()
+    } else {
+        fasta(&mut stdout, n, num_threads)?;
+        stdout.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_does_not_depend_on_threads() {
+        // 9 blocks of THREE, and a part of one
+        for &n in &[0, 1000, 100000] {
+            let mut expected = Vec::new();
+            fasta(&mut expected, n, 1).unwrap();
+            for &threads in &[2, 3, 8, 64, 1000] {
+                let mut out = Vec::new();
+                fasta(&mut out, n, threads).unwrap();
+                assert!(out == expected, "n={} threads={}", n, threads);
+            }
+        }
     }
 }
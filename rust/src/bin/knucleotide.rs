@@ -7,17 +7,13 @@
 // -C target-cpu=native -C panic=abort
 
 extern crate fxhash;
-// extern crate num_cpus;
-extern crate num_traits;
-// extern crate scoped_threadpool;
+extern crate wasm_bench;
 
-use num_traits::FromPrimitive;
-// use scoped_threadpool::Pool;
-use std::cmp::Eq;
-use std::hash::Hash;
-// use std::sync::mpsc;
-
-type Map<T> = fxhash::FxHashMap<T, u32>;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc;
+use std::thread;
+use wasm_bench::println;
 
 trait ShlXorMsk<T> {
     fn sh(a: T, x: u8, m: T) -> T;
@@ -33,7 +29,7 @@ macro_rules! impl_shl_xor_msk {
                 }
 
                 fn mask(len: usize) -> $prim {
-                    ((1u64 << 2 * len) - 1) as $prim
+                    (u64::MAX >> (64 - 2 * len)) as $prim
                 }
             }
         )*
@@ -52,97 +48,270 @@ fn match_key(k: u8) -> char {
     }
 }
 
-fn print_stat(h: Map<u8>, seq_len: usize) {
-    let total = h.values().sum::<u32>();
+/// A k-mer packed into 2 bits per nucleotide.
+trait Key: Default + Hash + Ord + ShlXorMsk<Self> + Into<u64> + Copy + Send {}
+
+impl<T: Default + Hash + Ord + ShlXorMsk<T> + Into<u64> + Copy + Send> Key for T {}
+
+/// Counts of the k-mers of a sequence.
+trait Counter<T: Key>: Send {
+    fn count(kmers: impl Iterator<Item = T>) -> Self;
+    fn get(&self, key: T) -> u32;
+    fn into_vec(self) -> Vec<(T, u32)>;
+    /// Add the counts of `other`.
+    fn merge(&mut self, other: Self);
+}
+
+/// Hash maps, `fxhash::FxHashMap` or `std::collections::HashMap` with SipHash.
+impl<T: Key, S: BuildHasher + Default + Send> Counter<T> for HashMap<T, u32, S> {
+    fn count(kmers: impl Iterator<Item = T>) -> Self {
+        let mut h = HashMap::default();
+        for a in kmers {
+            *h.entry(a).or_insert(0) += 1;
+        }
+        h
+    }
+
+    fn get(&self, key: T) -> u32 {
+        *HashMap::get(self, &key).unwrap_or(&0)
+    }
+
+    fn into_vec(self) -> Vec<(T, u32)> {
+        self.into_iter().collect()
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (k, v) in other {
+            *self.entry(k).or_insert(0) += v;
+        }
+    }
+}
+
+/// All k-mers sorted and then counted in runs.
+struct SortedCounter<T>(Vec<(T, u32)>);
+
+impl<T: Key> Counter<T> for SortedCounter<T> {
+    fn count(kmers: impl Iterator<Item = T>) -> Self {
+        let mut keys = kmers.collect::<Vec<_>>();
+        keys.sort_unstable();
+
+        let mut counts: Vec<(T, u32)> = Vec::new();
+        for a in keys {
+            match counts.last_mut() {
+                Some((k, v)) if *k == a => *v += 1,
+                _ => counts.push((a, 1)),
+            }
+        }
+        SortedCounter(counts)
+    }
+
+    fn get(&self, key: T) -> u32 {
+        match self.0.binary_search_by_key(&key, |&(k, _)| k) {
+            Ok(i) => self.0[i].1,
+            Err(_) => 0,
+        }
+    }
+
+    fn into_vec(self) -> Vec<(T, u32)> {
+        self.0
+    }
+
+    fn merge(&mut self, other: Self) {
+        let mut merged = Vec::with_capacity(self.0.len() + other.0.len());
+        let mut a = std::mem::take(&mut self.0).into_iter().peekable();
+        let mut b = other.0.into_iter().peekable();
+        loop {
+            let next = match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if x.0 == y.0 => {
+                    let (k, v) = a.next().unwrap();
+                    (k, v + b.next().unwrap().1)
+                }
+                (Some(x), Some(y)) if x.0 < y.0 => a.next().unwrap(),
+                (Some(_), Some(_)) | (None, Some(_)) => b.next().unwrap(),
+                (Some(_), None) => a.next().unwrap(),
+                (None, None) => break,
+            };
+            merged.push(next);
+        }
+        self.0 = merged;
+    }
+}
+
+/// Open addressing with linear probing in a power-of-two table which is kept
+/// at most half full, hashing by a multiplication with 2^64 / golden ratio. A
+/// count of 0 marks an empty slot.
+struct OpenCounter<T> {
+    slots: Vec<(T, u32)>,
+    len: usize,
+    shift: u32,
+}
+
+impl<T: Key> OpenCounter<T> {
+    fn with_capacity_log2(log2: u32) -> Self {
+        OpenCounter {
+            slots: vec![(T::default(), 0); 1 << log2],
+            len: 0,
+            shift: 64 - log2,
+        }
+    }
+
+    fn slot(&self, key: T) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut i = (key.into().wrapping_mul(0x9e37_79b9_7f4a_7c15) >> self.shift) as usize;
+        while self.slots[i].1 != 0 && self.slots[i].0 != key {
+            i = (i + 1) & mask;
+        }
+        i
+    }
+
+    fn add(&mut self, key: T, n: u32) {
+        let i = self.slot(key);
+        if self.slots[i].1 == 0 {
+            self.slots[i].0 = key;
+            self.len += 1;
+        }
+        self.slots[i].1 += n;
+
+        if 2 * self.len > self.slots.len() {
+            let mut grown = OpenCounter::with_capacity_log2(64 - self.shift + 1);
+            for &(k, v) in self.slots.iter().filter(|&&(_, v)| v != 0) {
+                grown.add(k, v);
+            }
+            *self = grown;
+        }
+    }
+}
+
+impl<T: Key> Counter<T> for OpenCounter<T> {
+    fn count(kmers: impl Iterator<Item = T>) -> Self {
+        let mut h = OpenCounter::with_capacity_log2(4);
+        for a in kmers {
+            h.add(a, 1);
+        }
+        h
+    }
+
+    fn get(&self, key: T) -> u32 {
+        self.slots[self.slot(key)].1
+    }
+
+    fn into_vec(self) -> Vec<(T, u32)> {
+        self.slots.into_iter().filter(|&(_, v)| v != 0).collect()
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (k, v) in other.into_vec() {
+            self.add(k, v);
+        }
+    }
+}
+
+/// How the k-mers are counted, with a counter for every key type.
+trait Backend {
+    type Counter<T: Key>: Counter<T>;
+}
+
+struct Fx;
+impl Backend for Fx {
+    type Counter<T: Key> = fxhash::FxHashMap<T, u32>;
+}
+
+struct Sip;
+impl Backend for Sip {
+    type Counter<T: Key> = HashMap<T, u32>;
+}
+
+struct Sorted;
+impl Backend for Sorted {
+    type Counter<T: Key> = SortedCounter<T>;
+}
+
+struct Open;
+impl Backend for Open {
+    type Counter<T: Key> = OpenCounter<T>;
+}
 
-    let mut vec = h.into_iter().collect::<Vec<_>>();
+fn print_stat<T: Key>(h: impl Counter<T>, seq_len: usize) {
+    let mut vec = h.into_vec();
+    let total = vec.iter().map(|&(_, v)| v).sum::<u32>();
     vec.sort_unstable_by(|&(ref a, x), &(ref b, y)| Ord::cmp(&(y, b), &(x, a)));
 
     for (k, v) in vec {
-        if seq_len == 1 {
-            println!("{} {:.3}", match_key(k), (100 * v) as f32 / total as f32);
-        } else {
-            println!(
-                "{}{} {:.3}",
-                match_key(k >> 2),
-                match_key(0b11 & k),
-                (100 * v) as f32 / total as f32
-            );
-        };
+        let k = k.into();
+        let seq = (0..seq_len)
+            .rev()
+            .map(|i| match_key(0b11 & (k >> (2 * i)) as u8))
+            .collect::<String>();
+        println!("{} {:.3}", seq, (100 * v) as f32 / total as f32);
     }
     println!();
 }
 
-fn print<T: FromPrimitive + Default + Hash + Eq + ShlXorMsk<T> + Copy>(h: Map<T>, seq: &str) {
-    let mask = T::from_u64((1u64 << (2 * seq.len() as u32)) - 1).unwrap();
+fn print<T: Key>(h: impl Counter<T>, seq: &str) {
+    let mask = T::mask(seq.len());
     let k = seq
         .to_ascii_lowercase()
         .as_bytes()
         .iter()
         .map(|x| 0b11u8 & x >> 1)
         .fold(T::default(), |acc, x| T::sh(acc, x, mask));
-    println!("{}\t{}", h.get(&k).unwrap_or(&0), seq);
+    println!("{}\t{}", h.get(k), seq);
 }
 
-fn freq<T: Default + Hash + Eq + ShlXorMsk<T> + Copy>(s_vec: &[u8], len: usize) -> Map<T> {
-    let mut h = Map::default();
+fn freq<B: Backend, T: Key>(s_vec: &[u8], len: usize) -> B::Counter<T> {
     let mask = T::mask(len);
     let mut it = s_vec.iter();
-    let mut a = it
+    let a = it
         .by_ref()
         .take(len - 1)
         .fold(T::default(), |acc, &x| T::sh(acc, x, mask));
-    for &x in it {
-        a = T::sh(a, x, mask);
-        *h.entry(a).or_insert(0) += 1;
-    }
-    h
-}
-
-// fn freq_par<T: Default + Hash + Eq + ShlXorMsk<T> + Copy + Send>(
-//     pool: &mut Pool,
-//     s_vec: &[u8],
-//     len: usize,
-// ) -> Map<T> {
-//     if s_vec.len() < 1000 {
-//         return freq(s_vec, len);
-//     }
-
-//     let num_partitions = pool.thread_count() as usize;
-//     let (tx, rx) = mpsc::channel();
-
-//     pool.scoped(|scope| {
-//         for i in 0..num_partitions {
-//             // split s_vec into partitions
-//             let start = s_vec.len() * i / num_partitions;
-//             let end = if i != num_partitions - 1 {
-//                 s_vec.len() * (i + 1) / num_partitions + len - 1
-//             } else {
-//                 s_vec.len()
-//             };
-//             let sub_vec = &s_vec[start..end];
-//             let tx = tx.clone();
-
-//             scope.execute(move || {
-//                 tx.send(freq::<T>(sub_vec, len)).unwrap();
-//             });
-//         }
-//     });
-
-//     {
-//         let mut merged = rx.recv().unwrap();
-
-//         // merge results
-//         for _ in 1..num_partitions {
-//             let map = rx.recv().unwrap();
-//             for (k, v) in map {
-//                 *merged.entry(k).or_insert(0) += v;
-//             }
-//         }
-
-//         merged
-//     }
-// }
+    B::Counter::count(it.scan(a, |a, &x| {
+        *a = T::sh(*a, x, mask);
+        Some(*a)
+    }))
+}
+
+/// `freq` with the sequence split into `num_threads` parts, counted in
+/// parallel and merged.
+fn freq_par<B: Backend, T: Key>(s_vec: &[u8], len: usize, num_threads: usize) -> B::Counter<T> {
+    if s_vec.len() < 1000 || num_threads == 1 {
+        return freq::<B, T>(s_vec, len);
+    }
+
+    // at least one k-mer per partition
+    let num_partitions = num_threads.min(s_vec.len() / len).max(1);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for i in 0..num_partitions {
+            // split s_vec into partitions, which overlap by len - 1 so that
+            // each k-mer ends in exactly one of them
+            let start = s_vec.len() * i / num_partitions;
+            let end = if i != num_partitions - 1 {
+                (s_vec.len() * (i + 1) / num_partitions + len - 1).min(s_vec.len())
+            } else {
+                s_vec.len()
+            };
+            let sub_vec = &s_vec[start..end];
+            let tx = tx.clone();
+
+            scope.spawn(move || {
+                tx.send(freq::<B, T>(sub_vec, len)).unwrap();
+            });
+        }
+    });
+
+    {
+        let mut merged = rx.recv().unwrap();
+
+        // merge results, the counts do not depend on the order
+        for _ in 1..num_partitions {
+            merged.merge(rx.recv().unwrap());
+        }
+
+        merged
+    }
+}
 
 fn get_seq<R: std::io::BufRead>(mut r: R, key: &[u8]) -> Vec<u8> {
     let mut res = Vec::with_capacity(65536);
@@ -171,32 +340,241 @@ fn get_seq<R: std::io::BufRead>(mut r: R, key: &[u8]) -> Vec<u8> {
     res
 }
 
-pub fn calc<R: std::io::BufRead>(r: R) {
-    let s_vec = get_seq(r, b">THREE");
+/// The narrowest key for k-mers of `$len`.
+macro_rules! with_key {
+    ($len:expr, $t:ident => $e:expr) => {
+        match $len {
+            0..=4 => {
+                type $t = u8;
+                $e
+            }
+            5..=8 => {
+                type $t = u16;
+                $e
+            }
+            9..=16 => {
+                type $t = u32;
+                $e
+            }
+            _ => {
+                type $t = u64;
+                $e
+            }
+        }
+    };
+}
 
-    let f1 = freq(&s_vec, 1);
-    print_stat(f1, 1);
+/// Print the frequencies of the k-mers of each length in `stats`, and then
+/// the count of each of `kmers`, counted on `num_threads` threads.
+fn calc<B: Backend, R: std::io::BufRead>(
+    r: R,
+    stats: &[usize],
+    kmers: &[String],
+    num_threads: usize,
+) {
+    let s_vec = get_seq(r, b">THREE");
 
-    let f2 = freq(&s_vec, 2);
-    print_stat(f2, 2);
+    for &len in stats {
+        with_key!(len, T => print_stat(freq_par::<B, T>(&s_vec, len, num_threads), len));
+    }
 
-    let f3 = freq::<u8>(&s_vec, 3);
-    print(f3, "GGT");
+    for seq in kmers {
+        with_key!(seq.len(), T => print(freq_par::<B, T>(&s_vec, seq.len(), num_threads), seq));
+    }
+}
 
-    let f4 = freq::<u8>(&s_vec, 4);
-    print(f4, "GGTA");
+/// Longest k-mer, which fills a `u64`.
+const MAX_K: usize = 32;
 
-    let f5 = freq::<u16>(&s_vec, 6);
-    print(f5, "GGTATT");
+struct Options {
+    backend: String,
+    stats: Vec<usize>,
+    kmers: Vec<String>,
+    num_threads: usize,
+}
 
-    let f6 = freq::<u32>(&s_vec, 12);
-    print(f6, "GGTATTTTAATT");
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        backend: "fx".to_string(),
+        stats: vec![1, 2],
+        kmers: [
+            "GGT",
+            "GGTA",
+            "GGTATT",
+            "GGTATTTTAATT",
+            "GGTATTTTAATTTATAGT",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+        num_threads: 1,
+    };
+
+    let mut positional = false;
+    let mut args = wasm_bench::host::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-m" | "--map" => opts.backend = args.next().unwrap_or_default(),
+            "-s" | "--stats" => {
+                let arg = args.next().unwrap_or_default();
+                opts.stats = arg
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| match s.parse() {
+                        Ok(len) if (1..=MAX_K).contains(&len) => Ok(len),
+                        _ => Err(format!("invalid k-mer length (1 to {}): {}", MAX_K, s)),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "-k" | "--kmers" => {
+                let arg = args.next().unwrap_or_default();
+                opts.kmers = arg
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| {
+                        let valid = s.len() <= MAX_K && s.bytes().all(|c| b"ACGTacgt".contains(&c));
+                        if valid {
+                            Ok(s.to_string())
+                        } else {
+                            Err(format!("invalid k-mer (up to {} of ACGT): {}", MAX_K, s))
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "-t" | "--threads" => {
+                let arg = args.next().unwrap_or_default();
+                opts.num_threads = match arg.parse() {
+                    Ok(t) if t > 0 => t,
+                    _ => return Err(format!("invalid thread count: {}", arg)),
+                };
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            // the argument of the benchmark, which is not used
+            _ if !positional => positional = true,
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    Ok(opts)
+}
 
-    let f7 = freq::<u64>(&s_vec, 18);
-    print(f7, "GGTATTTTAATTTATAGT");
+fn main() -> std::io::Result<()> {
+    let opts =
+        parse_args().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let calc = match opts.backend.as_str() {
+        "fx" => calc::<Fx, _>,
+        "sip" => calc::<Sip, _>,
+        "sorted" => calc::<Sorted, _>,
+        "open" => calc::<Open, _>,
+        b => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid map (fx, sip, sorted or open): {}", b),
+            ))
+        }
+    };
+
+    let stdin = wasm_bench::input::decompress(wasm_bench::host::stdin())?;
+    calc(
+        std::io::BufReader::new(stdin),
+        &opts.stats,
+        &opts.kmers,
+        opts.num_threads,
+    );
+    Ok(())
 }
 
-fn main() {
-    let stdin = std::io::stdin();
-    calc(stdin.lock());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pseudo-random sequence of nucleotides as 2-bit codes.
+    fn sequence(n: usize) -> Vec<u8> {
+        let mut x = 12345u64;
+        (0..n)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (x >> 62) as u8
+            })
+            .collect()
+    }
+
+    fn sorted<T: Key>(c: impl Counter<T>) -> Vec<(T, u32)> {
+        let mut v = c.into_vec();
+        v.sort_unstable();
+        v
+    }
+
+    fn check_backend<B: Backend>() {
+        let s_vec = sequence(20000);
+        for &len in &[1, 2, 3, 4] {
+            let expected = sorted(freq::<Sip, u8>(&s_vec, len));
+            let counter = freq::<B, u8>(&s_vec, len);
+            for &(k, v) in &expected {
+                assert_eq!(counter.get(k), v);
+            }
+            assert_eq!(sorted(counter), expected);
+        }
+        for &len in &[6, 12, 18] {
+            let expected = sorted(freq::<Sip, u64>(&s_vec, len));
+            assert_eq!(sorted(freq::<B, u64>(&s_vec, len)), expected);
+        }
+    }
+
+    #[test]
+    fn backends_count_like_hashmap() {
+        check_backend::<Fx>();
+        check_backend::<Sorted>();
+        check_backend::<Open>();
+    }
+
+    fn check_merge<B: Backend>() {
+        let s_vec = sequence(5000);
+        let expected = sorted(freq::<Sip, u16>(&s_vec, 6));
+        // the halves overlap by 5 so that every 6-mer is in one of them
+        let mut merged = freq::<B, u16>(&s_vec[..2505], 6);
+        merged.merge(freq::<B, u16>(&s_vec[2500..], 6));
+        assert_eq!(sorted(merged), expected);
+    }
+
+    #[test]
+    fn merge_keeps_counts() {
+        check_merge::<Fx>();
+        check_merge::<Sip>();
+        check_merge::<Sorted>();
+        check_merge::<Open>();
+    }
+
+    fn check_freq_par<B: Backend>() {
+        for &n in &[1000, 1001, 2000] {
+            let s_vec = sequence(n);
+            for &len in &[1, 2, 18] {
+                let expected = sorted(freq::<B, u64>(&s_vec, len));
+                for &threads in &[2, 7, 100, 1000, 5000] {
+                    assert_eq!(sorted(freq_par::<B, u64>(&s_vec, len, threads)), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn freq_par_counts_like_freq() {
+        check_freq_par::<Fx>();
+        check_freq_par::<Sip>();
+        check_freq_par::<Sorted>();
+        check_freq_par::<Open>();
+    }
+
+    #[test]
+    fn open_counter_keeps_counts_when_growing() {
+        let keys = (0..10000u32).flat_map(|k| std::iter::repeat_n(k * 7919, k as usize % 3 + 1));
+        let counter = OpenCounter::count(keys);
+        assert!(counter.slots.len() >= 2 * 10000);
+        for k in 0..10000u32 {
+            assert_eq!(counter.get(k * 7919), k % 3 + 1);
+        }
+        assert_eq!(counter.get(1), 0);
+        assert_eq!(counter.into_vec().len(), 10000);
+    }
 }
@@ -9,6 +9,7 @@
 extern crate generic_array;
 extern crate num_traits;
 extern crate numeric_array;
+extern crate wasm_bench;
 
 use generic_array::typenum::consts::U8;
 use numeric_array::NumericArray as Arr;
@@ -20,18 +21,23 @@ use std::io::Write;
 type Vecf64 = Arr<f64, U8>;
 type Constf64 = numeric_array::NumericConstant<f64>;
 
+/// Iteration limit by default.
 const MAX_ITER: usize = 50;
 const VLEN: usize = 8;
 
+/// The real and imaginary ranges of the image by default.
+const VIEWPORT: [f64; 4] = [-1.5, 0.5, -1., 1.];
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
 #[inline(always)]
-pub fn mbrot8(out: &mut u8, cr: Vecf64, ci: Constf64) {
+pub fn mbrot8(out: &mut u8, cr: Vecf64, ci: Constf64, max_iter: usize) {
     let mut zr = Arr::splat(0f64);
     let mut zi = Arr::splat(0f64);
     let mut tr = Arr::splat(0f64);
     let mut ti = Arr::splat(0f64);
     let mut absz = Arr::splat(0f64);
 
-    for _ in 0..MAX_ITER / 5 {
+    for _ in 0..max_iter / 5 {
         for _ in 0..5 {
             zi = (zr + zr) * zi + ci;
             zr = tr - ti + cr;
@@ -50,36 +56,283 @@ pub fn mbrot8(out: &mut u8, cr: Vecf64, ci: Constf64) {
     });
 }
 
+// Same as above with the 8 lanes split into four `f64x2` vectors.
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[inline(always)]
+pub fn mbrot8(out: &mut u8, cr: Vecf64, ci: Constf64, max_iter: usize) {
+    use std::arch::wasm32::*;
+
+    let cr = [
+        f64x2(cr[0], cr[1]),
+        f64x2(cr[2], cr[3]),
+        f64x2(cr[4], cr[5]),
+        f64x2(cr[6], cr[7]),
+    ];
+    let ci = f64x2_splat(ci.0);
+    let four = f64x2_splat(4.);
+
+    let mut zr = [f64x2_splat(0.); VLEN / 2];
+    let mut zi = [f64x2_splat(0.); VLEN / 2];
+    let mut tr = [f64x2_splat(0.); VLEN / 2];
+    let mut ti = [f64x2_splat(0.); VLEN / 2];
+    let mut absz = [f64x2_splat(0.); VLEN / 2];
+
+    for _ in 0..max_iter / 5 {
+        for _ in 0..5 {
+            for k in 0..VLEN / 2 {
+                zi[k] = f64x2_add(f64x2_mul(f64x2_add(zr[k], zr[k]), zi[k]), ci);
+                zr[k] = f64x2_add(f64x2_sub(tr[k], ti[k]), cr[k]);
+                tr[k] = f64x2_mul(zr[k], zr[k]);
+                ti[k] = f64x2_mul(zi[k], zi[k]);
+            }
+        }
+
+        for k in 0..VLEN / 2 {
+            absz[k] = f64x2_add(tr[k], ti[k]);
+        }
+        if absz.iter().all(|&t| i64x2_all_true(f64x2_gt(t, four))) {
+            return;
+        }
+    }
+
+    *out = absz.iter().enumerate().fold(0, |accu, (k, &t)| {
+        let mask = i64x2_bitmask(f64x2_le(t, four));
+        accu | (mask & 1) << (7 - 2 * k) | (mask >> 1) << (6 - 2 * k)
+    });
+}
+
+// `mbrot8` one lane at a time in plain f64, for `--format pgm` and `--dump`.
+// The operations and their order are the same, so the results are bit for
+// bit those of either `mbrot8`. Sharing the `NumericArray` operations with
+// `mbrot8` instead makes LLVM give up on keeping its inner loop in registers.
+fn mbrot8_lanes(out: &mut u8, cr: Vecf64, ci: Constf64, max_iter: usize) -> Lanes {
+    let ci = ci.0;
+    let mut zr = [0f64; VLEN];
+    let mut zi = [0f64; VLEN];
+    let mut tr = [0f64; VLEN];
+    let mut ti = [0f64; VLEN];
+    let mut lanes = Lanes {
+        absz: [0f64; VLEN],
+        escape: [max_iter; VLEN],
+    };
+
+    for n in 0..max_iter / 5 {
+        for m in 0..5 {
+            for k in 0..VLEN {
+                zi[k] = (zr[k] + zr[k]) * zi[k] + ci;
+                zr[k] = tr[k] - ti[k] + cr[k];
+                tr[k] = zr[k] * zr[k];
+                ti[k] = zi[k] * zi[k];
+                if lanes.escape[k] == max_iter && tr[k] + ti[k] > 4. {
+                    lanes.escape[k] = 5 * n + m;
+                }
+            }
+        }
+
+        for k in 0..VLEN {
+            lanes.absz[k] = tr[k] + ti[k];
+        }
+        if lanes.absz.iter().all(|&t| t > 4.) {
+            return lanes;
+        }
+    }
+
+    *out = lanes.absz.iter().enumerate().fold(0, |accu, (i, &t)| {
+        accu | if t <= 4. { 0x80 >> i } else { 0 }
+    });
+    lanes
+}
+
+struct Lanes {
+    /// The last |z|^2 computed.
+    absz: [f64; VLEN],
+    /// The first iteration whose |z|^2 exceeds 4, the iteration limit for
+    /// the points which do not escape.
+    escape: [usize; VLEN],
+}
+
+enum Format {
+    /// 1-bit bitmap of the points which do not escape.
+    P4,
+    /// 8-bit grayscale of the escape iterations, white for the points
+    /// which do not escape.
+    Pgm,
+}
+
+struct Options {
+    size: usize,
+    max_iter: usize,
+    /// `[x0, x1, y0, y1]`
+    viewport: [f64; 4],
+    format: Format,
+    dump: Option<usize>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        size: 200,
+        max_iter: MAX_ITER,
+        viewport: VIEWPORT,
+        format: Format::P4,
+        dump: None,
+    };
+
+    let mut args = wasm_bench::host::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-n" | "--iterations" => {
+                // the kernels look for escapes every 5 iterations
+                let arg = args.next().unwrap_or_default();
+                opts.max_iter = match arg.parse() {
+                    Ok(n) if n > 0 && n % 5 == 0 => n,
+                    _ => {
+                        return Err(format!(
+                            "invalid iteration limit (a multiple of 5): {}",
+                            arg
+                        ))
+                    }
+                };
+            }
+            "-v" | "--viewport" => {
+                let arg = args.next().unwrap_or_default();
+                let v = arg
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<Vec<f64>, _>>();
+                opts.viewport = match v.as_deref() {
+                    Ok(&[x0, x1, y0, y1]) if x0 < x1 && y0 < y1 => [x0, x1, y0, y1],
+                    _ => return Err(format!("invalid viewport (X0,X1,Y0,Y1): {}", arg)),
+                };
+            }
+            "-f" | "--format" => {
+                let arg = args.next().unwrap_or_default();
+                opts.format = match arg.as_str() {
+                    "p4" => Format::P4,
+                    "pgm" => Format::Pgm,
+                    _ => return Err(format!("invalid format: {}", arg)),
+                };
+            }
+            "-d" | "--dump" => {
+                opts.dump =
+                    Some(wasm_bench::dump::interval(args.next()).map_err(|e| e.to_string())?)
+            }
+            _ => opts.size = arg.parse().map_err(|_| format!("invalid size: {}", arg))?,
+        }
+    }
+    Ok(opts)
+}
+
 fn main() {
-    let size = std::env::args()
-        .nth(1)
-        .and_then(|n| n.parse().ok())
-        .unwrap_or(200);
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let max_iter = opts.max_iter;
+    let [x0, x1, y0, y1] = opts.viewport;
+
     // Round size to multiple of 8
-    let size = size / VLEN * VLEN;
+    let size = opts.size / VLEN * VLEN;
 
-    let inv = 2. / size as f64;
+    let dx = (x1 - x0) / size as f64;
+    let dy = (y1 - y0) / size as f64;
 
     let mut xloc = vec![Arr::splat(0f64); size / VLEN];
     for i in 0..size {
-        xloc[i / VLEN][i % VLEN] = i as f64 * inv - 1.5;
+        xloc[i / VLEN][i % VLEN] = i as f64 * dx + x0;
     }
+    let ci = |y: usize| numeric_array::NumericConstant(y as f64 * dy + y0);
+
+    let result = match (opts.dump, opts.format) {
+        (Some(k), _) => dump_rows(&xloc, ci, max_iter, k),
+        (None, Format::Pgm) => write_pgm(&xloc, ci, max_iter),
+        (None, Format::P4) => write_p4(&xloc, ci, max_iter),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn write_p4(
+    xloc: &[Vecf64],
+    ci: impl Fn(usize) -> Constf64,
+    max_iter: usize,
+) -> std::io::Result<()> {
+    let size = xloc.len() * VLEN;
 
-    let stdout_unlocked = std::io::stdout();
     // Main thread only can print to stdout
-    let mut stdout = stdout_unlocked.lock();
+    let mut stdout = wasm_bench::host::stdout();
 
-    println!("P4\n{} {}", size, size);
+    writeln!(stdout, "P4\n{} {}", size, size)?;
 
     let mut rows = vec![0; size * size / VLEN];
     rows.chunks_mut(size / VLEN)
         .enumerate()
         .for_each(|(y, out)| {
-            let ci = numeric_array::NumericConstant(y as f64 * inv - 1.);
+            let ci = ci(y);
             out.iter_mut()
                 .enumerate()
-                .for_each(|(i, inner_out)| mbrot8(inner_out, xloc[i], ci));
+                .for_each(|(i, inner_out)| mbrot8(inner_out, xloc[i], ci, max_iter));
         });
 
-    let _ = stdout.write_all(&rows);
+    stdout.write_all(&rows)
+}
+
+/// Write the escape iterations of the points as a PGM, scaled down to 255
+/// levels for iteration limits above 255.
+fn write_pgm(
+    xloc: &[Vecf64],
+    ci: impl Fn(usize) -> Constf64,
+    max_iter: usize,
+) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(wasm_bench::host::stdout());
+    let size = xloc.len() * VLEN;
+    let levels = max_iter.min(255);
+
+    writeln!(out, "P5\n{} {}\n{}", size, size, levels)?;
+    let mut row = Vec::with_capacity(size);
+    for y in 0..size {
+        let ci = ci(y);
+        let mut byte = 0;
+        row.clear();
+        for &cr in xloc.iter() {
+            let lanes = mbrot8_lanes(&mut byte, cr, ci, max_iter);
+            row.extend(lanes.escape.iter().map(|&e| (e * levels / max_iter) as u8));
+        }
+        out.write_all(&row)?;
+    }
+    out.flush()
+}
+
+/// Dump the real parts of the points, then the imaginary part and the last
+/// |z|^2 of every point of every `k`-th row, bit for bit.
+fn dump_rows(
+    xloc: &[Vecf64],
+    ci: impl Fn(usize) -> Constf64,
+    max_iter: usize,
+    k: usize,
+) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(wasm_bench::host::stdout());
+    let size = xloc.len() * VLEN;
+
+    let cr = xloc
+        .iter()
+        .flat_map(|x| x.iter().copied())
+        .collect::<Vec<_>>();
+    wasm_bench::dump::record(&mut out, format_args!("cr"), &cr)?;
+
+    for y in (0..size).step_by(k) {
+        let ci = ci(y);
+        let mut byte = 0;
+        let mut absz = Vec::with_capacity(size);
+        for &cr in xloc.iter() {
+            absz.extend_from_slice(&mbrot8_lanes(&mut byte, cr, ci, max_iter).absz);
+        }
+        wasm_bench::dump::record(&mut out, format_args!("row={} ci", y), &[ci.0])?;
+        wasm_bench::dump::record(&mut out, format_args!("row={} absz", y), &absz)?;
+    }
+    out.flush()
 }
@@ -1,10 +1,13 @@
+use std::convert::TryFrom;
 use std::default::Default;
 /// The Computer Language Benchmarks Game
 /// https://salsa.debian.org/benchmarksgame-team/benchmarksgame/
 ///
 /// Contributed by Ilia Schelokov
 use std::f64::consts::PI;
+use std::io::{self, ErrorKind, Write};
 use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+use wasm_bench::println;
 
 #[derive(Clone, Debug)]
 struct Vec3D(f64, f64, f64);
@@ -142,10 +145,148 @@ const STARTING_STATE: [Body; BODIES_COUNT] = [
     },
 ];
 
-/// Steps the simulation forward by one time-step.
-fn advance(bodies: &mut [Body; BODIES_COUNT], dt: f64, steps: usize) {
-    let mut d_positions: [Vec3D; INTERACTIONS] = Default::default();
-    let mut magnitudes = [0.; INTERACTIONS];
+/// A body as written in a bodies file: position in AU, velocity in AU per
+/// year and mass in solar masses.
+#[derive(serde::Deserialize)]
+struct BodySpec {
+    position: [f64; 3],
+    velocity: [f64; 3],
+    mass: f64,
+}
+
+impl From<&BodySpec> for Body {
+    fn from(b: &BodySpec) -> Body {
+        let [x, y, z] = b.position;
+        let [vx, vy, vz] = b.velocity;
+        Body {
+            position: Vec3D(x, y, z),
+            velocity: Vec3D(vx, vy, vz),
+            mass: b.mass * SOLAR_MASS,
+        }
+    }
+}
+
+/// Read the bodies of `path`, either a JSON array of
+/// `{"position": [x, y, z], "velocity": [vx, vy, vz], "mass": m}` objects or
+/// text with a `x y z vx vy vz m` line per body. In text, empty lines and
+/// lines starting with `#` are skipped. The first body is the one whose
+/// velocity offsets the momentum of the system.
+fn load_bodies(path: &str) -> io::Result<Vec<Body>> {
+    let invalid =
+        |msg: String| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path, msg));
+
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    let specs: Vec<BodySpec> = if text.trim_start().starts_with('[') {
+        serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?
+    } else {
+        let mut specs = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let v = line
+                .split_whitespace()
+                .map(|w| w.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|v| v.len() == 7)
+                .ok_or_else(|| invalid(format!("line {}: expected x y z vx vy vz m", i + 1)))?;
+            specs.push(BodySpec {
+                position: [v[0], v[1], v[2]],
+                velocity: [v[3], v[4], v[5]],
+                mass: v[6],
+            });
+        }
+        specs
+    };
+
+    if specs.is_empty() {
+        return Err(invalid("no bodies".to_string()));
+    }
+    Ok(specs.iter().map(Body::from).collect())
+}
+
+/// xorshift64*, so that a seed gives the same cluster on every target.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // the state must not be zero, or xorshift stays at zero; the one seed
+        // which would give it starts from a fixed state instead
+        match seed ^ 0x9e37_79b9_7f4a_7c15 {
+            0 => Rng(0x853c_49e6_748f_ea9b),
+            state => Rng(state),
+        }
+    }
+
+    /// Uniform in [0, 1).
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A sun and `n - 1` small bodies on roughly circular orbits in a thick disk
+/// between 1 and 50 AU around it.
+fn random_cluster(n: usize, seed: u64) -> Vec<Body> {
+    let mut rng = Rng::new(seed);
+    let mut bodies = vec![STARTING_STATE[0].clone()];
+
+    for _ in 1..n {
+        let r = 1. + 49. * rng.next();
+        let theta = 2. * PI * rng.next();
+        let z = (rng.next() - 0.5) * 0.1 * r;
+        let v = (SOLAR_MASS / r).sqrt();
+        bodies.push(Body {
+            position: Vec3D(r * theta.cos(), r * theta.sin(), z),
+            velocity: Vec3D(-v * theta.sin(), v * theta.cos(), 0.),
+            mass: (1e-7 + 1e-4 * rng.next()) * SOLAR_MASS,
+        });
+    }
+    bodies
+}
+
+/// Steps the simulation forward by `steps` time-steps.
+fn advance(bodies: &mut [Body], dt: f64, steps: usize) {
+    match <&mut [Body; BODIES_COUNT]>::try_from(&mut *bodies) {
+        // the five planets, in arrays of a fixed size which the loops are
+        // unrolled for
+        Ok(planets) => advance_with(
+            planets,
+            <[Vec3D; INTERACTIONS]>::default(),
+            [0.; INTERACTIONS],
+            dt,
+            steps,
+        ),
+        Err(_) => {
+            let pairs = bodies.len() * bodies.len().saturating_sub(1) / 2;
+            advance_with(
+                bodies,
+                vec![Vec3D::default(); pairs],
+                vec![0.; pairs],
+                dt,
+                steps,
+            )
+        }
+    }
+}
+
+/// `advance` with room for the vector and the magnitude between every pair
+/// of bodies.
+fn advance_with(
+    mut bodies: impl AsMut<[Body]>,
+    mut d_positions: impl AsMut<[Vec3D]>,
+    mut magnitudes: impl AsMut<[f64]>,
+    dt: f64,
+    steps: usize,
+) {
+    let bodies = bodies.as_mut();
+    let d_positions = d_positions.as_mut();
+    let magnitudes = magnitudes.as_mut();
 
     for _ in 0..steps {
         // Vectors between each pair of bodies.
@@ -164,7 +305,7 @@ fn advance(bodies: &mut [Body; BODIES_COUNT], dt: f64, steps: usize) {
 
         // Apply every other body's gravitation to each body's velocity.
         let mut k = 0;
-        for i in 0..BODIES_COUNT - 1 {
+        for i in 0..bodies.len() - 1 {
             let (body1, rest) = bodies[i..].split_first_mut().unwrap();
             for body2 in rest {
                 let d_pos = &d_positions[k];
@@ -182,17 +323,154 @@ fn advance(bodies: &mut [Body; BODIES_COUNT], dt: f64, steps: usize) {
     }
 }
 
+/// How the bodies are moved forward in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Integrator {
+    /// Symplectic Euler: velocities first, then positions with the new
+    /// velocities. This is what `advance` does.
+    Euler,
+    /// Kick-drift-kick leapfrog, second order and symplectic.
+    Leapfrog,
+    /// Classical fourth-order Runge-Kutta, accurate but not symplectic, so
+    /// its energy drifts steadily.
+    Rk4,
+}
+
+/// Gravitational acceleration of every body at `positions`.
+fn accelerations(positions: &[Vec3D], masses: &[f64], acc: &mut [Vec3D]) {
+    for a in acc.iter_mut() {
+        *a = Vec3D::default();
+    }
+    for i in 0..positions.len() {
+        let (a1, rest) = acc[i..].split_first_mut().unwrap();
+        for (j, a2) in (i + 1..).zip(rest) {
+            let d_pos = &positions[i] - &positions[j];
+            let mag = d_pos.magnitude(1.);
+            *a1 -= &d_pos * (masses[j] * mag);
+            *a2 += &d_pos * (masses[i] * mag);
+        }
+    }
+}
+
+fn positions(bodies: &[Body]) -> Vec<Vec3D> {
+    bodies.iter().map(|b| b.position.clone()).collect()
+}
+
+fn velocities(bodies: &[Body]) -> Vec<Vec3D> {
+    bodies.iter().map(|b| b.velocity.clone()).collect()
+}
+
+fn masses(bodies: &[Body]) -> Vec<f64> {
+    bodies.iter().map(|b| b.mass).collect()
+}
+
+fn leapfrog(bodies: &mut [Body], dt: f64, steps: usize) {
+    let masses = masses(bodies);
+    let mut x = positions(bodies);
+    let mut v = velocities(bodies);
+    let mut acc = vec![Vec3D::default(); bodies.len()];
+
+    accelerations(&x, &masses, &mut acc);
+    for _ in 0..steps {
+        for (v, a) in v.iter_mut().zip(acc.iter()) {
+            *v += a * (0.5 * dt);
+        }
+        for (x, v) in x.iter_mut().zip(v.iter()) {
+            *x += v * dt;
+        }
+        accelerations(&x, &masses, &mut acc);
+        for (v, a) in v.iter_mut().zip(acc.iter()) {
+            *v += a * (0.5 * dt);
+        }
+    }
+
+    for (body, (x, v)) in bodies.iter_mut().zip(x.into_iter().zip(v)) {
+        body.position = x;
+        body.velocity = v;
+    }
+}
+
+/// `(a + 2b + 2c + d) / 6`, the weighted mean of the Runge-Kutta stages.
+fn rk4_mean(a: &Vec3D, b: &Vec3D, c: &Vec3D, d: &Vec3D) -> Vec3D {
+    Vec3D(
+        (a.0 + 2. * b.0 + 2. * c.0 + d.0) / 6.,
+        (a.1 + 2. * b.1 + 2. * c.1 + d.1) / 6.,
+        (a.2 + 2. * b.2 + 2. * c.2 + d.2) / 6.,
+    )
+}
+
+/// Derivative of positions and velocities of the state `(x, v)` moved by `h`
+/// along the derivative `(kx, kv)` of the previous stage.
+struct Stage {
+    dx: Vec<Vec3D>,
+    dv: Vec<Vec3D>,
+}
+
+impl Stage {
+    fn new(n: usize) -> Stage {
+        Stage {
+            dx: vec![Vec3D::default(); n],
+            dv: vec![Vec3D::default(); n],
+        }
+    }
+
+    fn eval(
+        &mut self,
+        x: &[Vec3D],
+        v: &[Vec3D],
+        masses: &[f64],
+        prev: &Stage,
+        h: f64,
+        xt: &mut [Vec3D],
+    ) {
+        for i in 0..x.len() {
+            xt[i] = &x[i] + &(&prev.dx[i] * h);
+            self.dx[i] = &v[i] + &(&prev.dv[i] * h);
+        }
+        accelerations(xt, masses, &mut self.dv);
+    }
+}
+
+fn rk4(bodies: &mut [Body], dt: f64, steps: usize) {
+    let n = bodies.len();
+    let masses = masses(bodies);
+    let mut x = positions(bodies);
+    let mut v = velocities(bodies);
+    let mut xt = vec![Vec3D::default(); n];
+    let mut k = [Stage::new(n), Stage::new(n), Stage::new(n), Stage::new(n)];
+
+    for _ in 0..steps {
+        let [k1, k2, k3, k4] = &mut k;
+
+        k1.dx.clone_from_slice(&v);
+        accelerations(&x, &masses, &mut k1.dv);
+        k2.eval(&x, &v, &masses, k1, 0.5 * dt, &mut xt);
+        k3.eval(&x, &v, &masses, k2, 0.5 * dt, &mut xt);
+        k4.eval(&x, &v, &masses, k3, dt, &mut xt);
+
+        for i in 0..n {
+            x[i] += &rk4_mean(&k1.dx[i], &k2.dx[i], &k3.dx[i], &k4.dx[i]) * dt;
+            v[i] += &rk4_mean(&k1.dv[i], &k2.dv[i], &k3.dv[i], &k4.dv[i]) * dt;
+        }
+    }
+
+    for (body, (x, v)) in bodies.iter_mut().zip(x.into_iter().zip(v)) {
+        body.position = x;
+        body.velocity = v;
+    }
+}
+
 /// Adjust the Sun's velocity to offset system momentum.
-fn offset_momentum(bodies: &mut [Body; BODIES_COUNT]) {
+fn offset_momentum(bodies: &mut [Body]) {
     let (sun, planets) = bodies.split_first_mut().unwrap();
     sun.velocity = Default::default();
     for planet in planets {
-        sun.velocity -= &planet.velocity * (planet.mass / SOLAR_MASS);
+        sun.velocity -= &planet.velocity * (planet.mass / sun.mass);
     }
 }
 
 /// Print the system energy.
-fn compute_energy(bodies: &mut [Body; BODIES_COUNT]) -> f64 {
+fn compute_energy(bodies: &[Body]) -> f64 {
     let mut energy = 0.;
     for (i, body1) in bodies.iter().enumerate() {
         // Add the kinetic energy for each body.
@@ -206,17 +484,161 @@ fn compute_energy(bodies: &mut [Body; BODIES_COUNT]) -> f64 {
     energy
 }
 
-fn main() {
-    let ncycles = std::env::args_os()
-        .nth(1)
-        .and_then(|s| s.into_string().ok())
-        .and_then(|n| n.parse().ok())
-        .unwrap_or(1000);
+fn main() -> io::Result<()> {
+    let mut ncycles = 1000;
+    let mut file = None;
+    let mut random = None;
+    let mut seed = 0;
+    let mut integrator = Integrator::Euler;
+    let mut sample_every: Option<usize> = None;
+    let mut dump = None;
+    let mut exact = false;
 
-    let mut bodies = STARTING_STATE;
+    let invalid = |what: &str, arg: &str| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid {}: {}", what, arg),
+        )
+    };
+
+    let mut args = wasm_bench::host::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" | "--bodies" => file = Some(args.next().unwrap_or_default()),
+            "-r" | "--random" => {
+                let arg = args.next().unwrap_or_default();
+                random = match arg.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(invalid("body count", &arg)),
+                };
+            }
+            "-s" | "--seed" => {
+                let arg = args.next().unwrap_or_default();
+                seed = arg.parse().map_err(|_| invalid("seed", &arg))?;
+            }
+            "-i" | "--integrator" => {
+                let arg = args.next().unwrap_or_default();
+                integrator = match arg.as_str() {
+                    "euler" => Integrator::Euler,
+                    "leapfrog" => Integrator::Leapfrog,
+                    "rk4" => Integrator::Rk4,
+                    _ => return Err(invalid("integrator", &arg)),
+                };
+            }
+            "-e" | "--sample-every" => {
+                let arg = args.next().unwrap_or_default();
+                sample_every = match arg.parse() {
+                    Ok(k) if k > 0 => Some(k),
+                    _ => return Err(invalid("sampling interval", &arg)),
+                };
+            }
+            "-d" | "--dump" => dump = Some(wasm_bench::dump::interval(args.next())?),
+            "-x" | "--exact" => exact = true,
+            _ => ncycles = arg.parse().map_err(|_| invalid("step count", &arg))?,
+        }
+    }
+
+    // every digit of the energies instead of the nine decimals of the benchmark
+    let energy = |bodies: &[Body]| {
+        let e = compute_energy(bodies);
+        if exact {
+            format!("{:e}", e)
+        } else {
+            format!("{:.9}", e)
+        }
+    };
+
+    let mut bodies = match (file, random) {
+        (Some(file), _) => load_bodies(&file)?,
+        (None, Some(n)) => random_cluster(n, seed),
+        (None, None)
+            if integrator == Integrator::Euler && sample_every.is_none() && dump.is_none() =>
+        {
+            let mut bodies = STARTING_STATE;
+
+            offset_momentum(&mut bodies);
+            println!("{}", energy(&bodies));
+            advance(&mut bodies, 0.01, ncycles);
+            println!("{}", energy(&bodies));
+            return Ok(());
+        }
+        (None, None) => STARTING_STATE.to_vec(),
+    };
+
+    let integrate = |bodies: &mut [Body], steps: usize| match integrator {
+        Integrator::Euler => advance(bodies, 0.01, steps),
+        Integrator::Leapfrog => leapfrog(bodies, 0.01, steps),
+        Integrator::Rk4 => rk4(bodies, 0.01, steps),
+    };
 
     offset_momentum(&mut bodies);
-    println!("{:.9}", compute_energy(&mut bodies));
-    advance(&mut bodies, 0.01, ncycles);
-    println!("{:.9}", compute_energy(&mut bodies));
+    if let Some(k) = dump {
+        // the state of every body and the energy every `k` steps, bit for bit
+        let mut out = io::BufWriter::new(wasm_bench::host::stdout());
+        let mut step = 0;
+        loop {
+            for (i, b) in bodies.iter().enumerate() {
+                let (p, v) = (&b.position, &b.velocity);
+                wasm_bench::dump::record(
+                    &mut out,
+                    format_args!("step={} body={}", step, i),
+                    &[p.0, p.1, p.2, v.0, v.1, v.2],
+                )?;
+            }
+            let e = compute_energy(&bodies);
+            wasm_bench::dump::record(&mut out, format_args!("step={} energy", step), &[e])?;
+            if step == ncycles {
+                return out.flush();
+            }
+            let n = k.min(ncycles - step);
+            integrate(&mut bodies, n);
+            step += n;
+        }
+    }
+    match sample_every {
+        None => {
+            println!("{}", energy(&bodies));
+            integrate(&mut bodies, ncycles);
+            println!("{}", energy(&bodies));
+        }
+        Some(k) => {
+            // "step energy drift", with every digit of the energy so that
+            // runtimes can be compared to the last bit
+            let e0 = compute_energy(&bodies);
+            println!("0 {:?} {:e}", e0, 0.);
+            let mut step = 0;
+            while step < ncycles {
+                let n = k.min(ncycles - step);
+                integrate(&mut bodies, n);
+                step += n;
+                let e = compute_energy(&bodies);
+                println!("{} {:?} {:e}", step, e, (e - e0) / e0.abs());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_seed_gives_random_numbers() {
+        for seed in [0, 1, 0x9e37_79b9_7f4a_7c15, u64::MAX] {
+            let mut rng = Rng::new(seed);
+            let x = (0..100).map(|_| rng.next()).collect::<Vec<_>>();
+            assert!(x.iter().all(|x| (0.0..1.0).contains(x)), "seed {}", seed);
+            assert!(x.windows(2).all(|w| w[0] != w[1]), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn random_cluster_of_every_seed() {
+        for seed in [0, 11400714819323198485] {
+            let bodies = random_cluster(10, seed);
+            assert_eq!(bodies.len(), 10);
+            assert!(bodies[1..].iter().all(|b| b.position.sum_squares() >= 1.0));
+        }
+    }
 }
@@ -5,6 +5,7 @@
 // Inspired by C++ #2 implementation Adam Kewley
 
 extern crate memchr;
+extern crate wasm_bench;
 
 use memchr::memchr;
 use std::cmp;
@@ -112,7 +113,79 @@ mod ssse3 {
 #[cfg(target_feature = "ssse3")]
 use ssse3::*;
 
-#[cfg(not(target_feature = "ssse3"))]
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod simd128 {
+    use std::arch::wasm32::*;
+
+    use super::KNUCLEOTIDE_MAPPING;
+
+    /// reverse bytes and complement each byte
+    ///
+    /// Unlike `_mm_shuffle_epi8`, `u8x16_swizzle` yields 0 for every index
+    /// >= 16, so each lookup table only picks up its own half of the range.
+    #[rustfmt::skip]
+    fn reverse_chunks_simd(v: v128) -> v128 {
+        let v = i8x16_shuffle::<15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0>(v, v);
+        let v = v128_and(v, u8x16_splat(0x1f));
+
+        let lt16_lut = u8x16(
+            0,          84 /* T */, 86 /* V */, 71 /* G */,
+            72 /* H */, 0,          0,          67 /* C */,
+            68 /* D */, 0,          10,         77 /* M */,
+            0,          75 /* K */, 78 /* N */, 0,
+        );
+        let lt16_vals = u8x16_swizzle(lt16_lut, v);
+
+        let g16_els = u8x16_sub(v, u8x16_splat(0x10));
+        let g16_lut = u8x16(
+            0,          0,          89 /* Y */, 83 /* S */,
+            65 /* A */, 65 /* A */, 66 /* B */, 87 /* W */,
+            0,          82 /* R */, 0,          0,
+            0,          0,          0,          0,
+        );
+        let g16_vals = u8x16_swizzle(g16_lut, g16_els);
+
+        v128_or(lt16_vals, g16_vals)
+    }
+
+    pub fn reverse_chunks(left: &mut [u8], right: &mut [u8]) {
+        debug_assert_eq!(left.len(), right.len());
+
+        unsafe {
+            let mut len = left.len();
+            let mut pl = left.as_mut_ptr();
+            let mut pr = right.as_mut_ptr().add(right.len());
+
+            while len >= 16 {
+                pr = pr.sub(16);
+                let l = v128_load(pl as _);
+                let r = v128_load(pr as _);
+                v128_store(pr as _, reverse_chunks_simd(l));
+                v128_store(pl as _, reverse_chunks_simd(r));
+                pl = pl.add(16);
+
+                len -= 16;
+            }
+
+            for _ in 0..len {
+                pr = pr.sub(1);
+                let l = *pl;
+                let r = *pr;
+                *pr = KNUCLEOTIDE_MAPPING[l as usize];
+                *pl = KNUCLEOTIDE_MAPPING[r as usize];
+                pl = pl.add(1);
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use simd128::*;
+
+#[cfg(not(any(
+    target_feature = "ssse3",
+    all(target_arch = "wasm32", target_feature = "simd128")
+)))]
 mod fallback {
     use super::KNUCLEOTIDE_MAPPING;
 
@@ -126,7 +199,10 @@ mod fallback {
     }
 }
 
-#[cfg(not(target_feature = "ssse3"))]
+#[cfg(not(any(
+    target_feature = "ssse3",
+    all(target_arch = "wasm32", target_feature = "simd128")
+)))]
 use fallback::*;
 
 struct Sequence<'a> {
@@ -300,13 +376,11 @@ fn main() -> io::Result<()> {
     let mut stdout = unsafe { File::from_raw_fd(1) };
 
     #[cfg(not(unix))]
-    let stdin = io::stdin();
-    #[cfg(not(unix))]
-    let stdin = stdin.lock();
+    let stdin = wasm_bench::host::stdin();
     #[cfg(not(unix))]
-    let mut stdout = io::stdout();
+    let mut stdout = wasm_bench::host::stdout();
 
-    let mut reader = SequenceReader::new(stdin);
+    let mut reader = SequenceReader::new(wasm_bench::input::decompress(stdin)?);
 
     while let Some(seq) = reader.next() {
         let mut seq = seq?;
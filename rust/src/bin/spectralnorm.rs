@@ -9,125 +9,363 @@
 // contributed by Andre Bogus
 
 // extern crate rayon;
+extern crate wasm_bench;
 // use rayon::prelude::*;
+use std::io::{self, Write};
 use std::ops::*;
+use wasm_bench::println;
 
-#[derive(Clone, Copy)]
-struct F64x2(f64, f64);
+/// A vector of `LANES` f64s, the unit the kernel computes in. `f64` is the
+/// one-lane vector, `F64x2` the natural one for wasm SIMD, and `F64x4` and
+/// `F64x8` are made of two vectors of half their width, which the compiler may
+/// or may not map to wider SIMD registers.
+pub trait Simd<const LANES: usize>:
+    Copy + Add<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+{
+    fn splat(x: f64) -> Self;
+    fn from_array(a: [f64; LANES]) -> Self;
+    fn to_array(self) -> [f64; LANES];
+    /// Sum of the lanes.
+    fn sum(self) -> f64;
+}
 
-impl F64x2 {
-    pub fn splat(x: f64) -> F64x2 {
-        F64x2(x, x)
+impl Simd<1> for f64 {
+    fn splat(x: f64) -> f64 {
+        x
     }
-    pub fn new(a: f64, b: f64) -> F64x2 {
-        F64x2(a, b)
+    fn from_array(a: [f64; 1]) -> f64 {
+        a[0]
     }
-    pub fn write_to_slice_unaligned(self, slice: &mut [f64]) {
-        slice[0] = self.0;
-        slice[1] = self.1;
+    fn to_array(self) -> [f64; 1] {
+        [self]
     }
-    pub fn sum(self) -> f64 {
-        let mut s = [0f64; 2];
-        self.write_to_slice_unaligned(&mut s);
-        s[0] + s[1]
+    fn sum(self) -> f64 {
+        self
     }
 }
 
-impl Add for F64x2 {
-    type Output = Self;
-    fn add(self, rhs: Self) -> Self {
-        F64x2(self.0 + rhs.0, self.1 + rhs.1)
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+mod scalar {
+    use super::Simd;
+    use std::ops::*;
+
+    #[derive(Clone, Copy)]
+    pub struct F64x2(f64, f64);
+
+    impl Simd<2> for F64x2 {
+        fn splat(x: f64) -> F64x2 {
+            F64x2(x, x)
+        }
+        fn from_array(a: [f64; 2]) -> F64x2 {
+            F64x2(a[0], a[1])
+        }
+        fn to_array(self) -> [f64; 2] {
+            [self.0, self.1]
+        }
+        fn sum(self) -> f64 {
+            self.0 + self.1
+        }
+    }
+
+    impl Add for F64x2 {
+        type Output = Self;
+        fn add(self, rhs: Self) -> Self {
+            F64x2(self.0 + rhs.0, self.1 + rhs.1)
+        }
+    }
+    impl Mul for F64x2 {
+        type Output = Self;
+        fn mul(self, rhs: Self) -> Self {
+            F64x2(self.0 * rhs.0, self.1 * rhs.1)
+        }
+    }
+    impl Div for F64x2 {
+        type Output = Self;
+        fn div(self, rhs: Self) -> Self {
+            F64x2(self.0 / rhs.0, self.1 / rhs.1)
+        }
     }
 }
-impl Mul for F64x2 {
-    type Output = Self;
-    fn mul(self, rhs: Self) -> Self {
-        F64x2(self.0 * rhs.0, self.1 * rhs.1)
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+use scalar::F64x2;
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod simd128 {
+    use super::Simd;
+    use std::arch::wasm32::*;
+    use std::ops::*;
+
+    #[derive(Clone, Copy)]
+    pub struct F64x2(v128);
+
+    impl Simd<2> for F64x2 {
+        fn splat(x: f64) -> F64x2 {
+            F64x2(f64x2_splat(x))
+        }
+        fn from_array(a: [f64; 2]) -> F64x2 {
+            F64x2(f64x2(a[0], a[1]))
+        }
+        fn to_array(self) -> [f64; 2] {
+            [
+                f64x2_extract_lane::<0>(self.0),
+                f64x2_extract_lane::<1>(self.0),
+            ]
+        }
+        fn sum(self) -> f64 {
+            f64x2_extract_lane::<0>(self.0) + f64x2_extract_lane::<1>(self.0)
+        }
+    }
+
+    impl Add for F64x2 {
+        type Output = Self;
+        fn add(self, rhs: Self) -> Self {
+            F64x2(f64x2_add(self.0, rhs.0))
+        }
+    }
+    impl Mul for F64x2 {
+        type Output = Self;
+        fn mul(self, rhs: Self) -> Self {
+            F64x2(f64x2_mul(self.0, rhs.0))
+        }
+    }
+    impl Div for F64x2 {
+        type Output = Self;
+        fn div(self, rhs: Self) -> Self {
+            F64x2(f64x2_div(self.0, rhs.0))
+        }
     }
 }
-impl Div for F64x2 {
-    type Output = Self;
-    fn div(self, rhs: Self) -> Self {
-        F64x2(self.0 / rhs.0, self.1 / rhs.1)
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use simd128::F64x2;
+
+/// `$name` of `$lanes` lanes as two `$half` vectors.
+macro_rules! pair {
+    ($name:ident, $half:ident, $lanes:expr) => {
+        #[derive(Clone, Copy)]
+        pub struct $name($half, $half);
+
+        impl Simd<$lanes> for $name {
+            fn splat(x: f64) -> $name {
+                $name($half::splat(x), $half::splat(x))
+            }
+            fn from_array(a: [f64; $lanes]) -> $name {
+                $name(
+                    $half::from_array(std::array::from_fn(|k| a[k])),
+                    $half::from_array(std::array::from_fn(|k| a[$lanes / 2 + k])),
+                )
+            }
+            fn to_array(self) -> [f64; $lanes] {
+                let mut a = [0.0; $lanes];
+                let (lo, hi) = a.split_at_mut($lanes / 2);
+                lo.copy_from_slice(&self.0.to_array());
+                hi.copy_from_slice(&self.1.to_array());
+                a
+            }
+            fn sum(self) -> f64 {
+                self.0.sum() + self.1.sum()
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                $name(self.0 + rhs.0, self.1 + rhs.1)
+            }
+        }
+        impl Mul for $name {
+            type Output = Self;
+            fn mul(self, rhs: Self) -> Self {
+                $name(self.0 * rhs.0, self.1 * rhs.1)
+            }
+        }
+        impl Div for $name {
+            type Output = Self;
+            fn div(self, rhs: Self) -> Self {
+                $name(self.0 / rhs.0, self.1 / rhs.1)
+            }
+        }
+    };
+}
+
+pair!(F64x4, F64x2, 4);
+pair!(F64x8, F64x4, 8);
+
+fn main() -> io::Result<()> {
+    let mut n = 100;
+    let mut width = 2;
+    let mut dump = false;
+    let mut exact = false;
+
+    let mut args = wasm_bench::host::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-w" | "--width" => {
+                let arg = args.next().unwrap_or_default();
+                width = match arg.parse() {
+                    Ok(w @ (1 | 2 | 4 | 8)) => w,
+                    _ => {
+                        return Err(invalid_input(format!(
+                            "invalid width (1, 2, 4 or 8): {}",
+                            arg
+                        )))
+                    }
+                };
+            }
+            "-d" | "--dump" => dump = true,
+            "-x" | "--exact" => exact = true,
+            _ if arg.starts_with('-') => {
+                return Err(invalid_input(format!("unknown option: {}", arg)))
+            }
+            _ => {
+                n = arg
+                    .parse()
+                    .map_err(|_| invalid_input(format!("invalid n: {}", arg)))?
+            }
+        }
+    }
+    if n % width != 0 {
+        return Err(invalid_input(format!(
+            "{} is not a multiple of the width {}",
+            n, width
+        )));
+    }
+
+    match width {
+        1 => run::<f64, 1>(n, dump, exact),
+        2 => run::<F64x2, 2>(n, dump, exact),
+        4 => run::<F64x4, 4>(n, dump, exact),
+        _ => run::<F64x8, 8>(n, dump, exact),
     }
 }
 
-fn main() {
-    let n = std::env::args()
-        .nth(1)
-        .and_then(|n| n.parse().ok())
-        .unwrap_or(100);
-    let answer = spectralnorm(n);
-    println!("{:.9}", answer);
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn run<V: Simd<L>, const L: usize>(n: usize, dump: bool, exact: bool) -> io::Result<()> {
+    if !dump {
+        let answer = spectralnorm::<V, L>(n, |_, _, _| {});
+        if exact {
+            // every digit instead of the nine decimals of the benchmark
+            println!("{:e}", answer);
+        } else {
+            println!("{:.9}", answer);
+        }
+        return Ok(());
+    }
+
+    // `u` and `v` after every iteration and the answer, bit for bit
+    let mut out = io::BufWriter::new(wasm_bench::host::stdout());
+    let mut result = Ok(());
+    let answer = spectralnorm::<V, L>(n, |iter, u, v| {
+        for (name, x) in [("u", u), ("v", v)] {
+            let x = x.iter().flat_map(|x| x.to_array()).collect::<Vec<_>>();
+            if result.is_ok() {
+                result =
+                    wasm_bench::dump::record(&mut out, format_args!("iter={} {}", iter, name), &x);
+            }
+        }
+    });
+    result?;
+    wasm_bench::dump::record(&mut out, format_args!("answer"), &[answer])?;
+    out.flush()
 }
 
-fn spectralnorm(n: usize) -> f64 {
-    // Group all vectors in pairs of two for SIMD convenience.
-    assert!(n % 2 == 0, "only even lengths are accepted");
-    let mut u = vec![F64x2::splat(1.0); n / 2];
-    let mut v = vec![F64x2::splat(0.0); n / 2];
-    let mut tmp = vec![F64x2::splat(0.0); n / 2];
+/// `inspect` is called with the iteration and `u` and `v` after each of the
+/// 10 iterations.
+fn spectralnorm<V: Simd<L>, const L: usize>(
+    n: usize,
+    mut inspect: impl FnMut(usize, &[V], &[V]),
+) -> f64 {
+    // Group all vectors in vectors of L lanes for SIMD convenience.
+    assert!(n % L == 0, "only multiples of the width are accepted");
+    let mut u = vec![V::splat(1.0); n / L];
+    let mut v = vec![V::splat(0.0); n / L];
+    let mut tmp = vec![V::splat(0.0); n / L];
 
-    for _ in 0..10 {
+    for iter in 0..10 {
         mult_at_av(&u, &mut v, &mut tmp);
         mult_at_av(&v, &mut u, &mut tmp);
+        inspect(iter, &u, &v);
     }
 
     (dot(&u, &v) / dot(&v, &v)).sqrt()
 }
 
-fn mult_at_av(v: &[F64x2], out: &mut [F64x2], tmp: &mut [F64x2]) {
+fn mult_at_av<V: Simd<L>, const L: usize>(v: &[V], out: &mut [V], tmp: &mut [V]) {
     mult(v, tmp, a);
     mult(tmp, out, |i, j| a(j, i));
 }
 
-fn mult<F>(v: &[F64x2], out: &mut [F64x2], a: F)
+fn mult<V, F, const L: usize>(v: &[V], out: &mut [V], a: F)
 where
-    F: Fn([usize; 2], [usize; 2]) -> F64x2 + Sync,
+    V: Simd<L>,
+    F: Fn(usize, usize) -> f64,
 {
-    // Parallelize along the output vector, with each pair of slots as a parallelism unit.
+    // Parallelize along the output vector, with each vector of L slots as a parallelism unit.
     out.iter_mut().enumerate().for_each(|(i, slot)| {
-        // We're computing everything in chunks of two so the indces of slot[0] and slot[1] are 2*i
-        // and 2*i + 1.
-        let i = 2 * i;
-        let (i0, i1) = ([i; 2], [i + 1; 2]);
-
-        // Each slot in the pair gets its own sum, which is further computed in two f64 lanes (which
-        // are summed at the end.
-        let (mut sum0, mut sum1) = (F64x2::splat(0.0), F64x2::splat(0.0));
+        // We're computing everything in chunks of L so the indices of the slots are L*i to
+        // L*i + L - 1.
+        let i = L * i;
+
+        // Each slot gets its own sum, which is further computed in L f64 lanes (which are summed
+        // at the end).
+        let mut sums = [V::splat(0.0); L];
         for (j, x) in v.iter().enumerate() {
-            let j = [2 * j, 2 * j + 1];
-            div_and_add(*x, a(i0, j), a(i1, j), &mut sum0, &mut sum1);
+            let j = L * j;
+            let a =
+                std::array::from_fn(|k| V::from_array(std::array::from_fn(|l| a(i + k, j + l))));
+            div_and_add(*x, a, &mut sums);
         }
 
-        // Sum the two lanes for each slot.
-        *slot = F64x2::new(sum0.sum(), sum1.sum());
+        // Sum the L lanes for each slot.
+        *slot = V::from_array(sums.map(V::sum));
     });
 }
 
-fn a(i: [usize; 2], j: [usize; 2]) -> F64x2 {
-    F64x2::new(
-        ((i[0] + j[0]) * (i[0] + j[0] + 1) / 2 + i[0] + 1) as f64,
-        ((i[1] + j[1]) * (i[1] + j[1] + 1) / 2 + i[1] + 1) as f64,
-    )
+fn a(i: usize, j: usize) -> f64 {
+    ((i + j) * (i + j + 1) / 2 + i + 1) as f64
 }
 
-fn dot(v: &[F64x2], u: &[F64x2]) -> f64 {
-    // Vectorised form of dot product: (1) compute dot across two lanes.
+fn dot<V: Simd<L>, const L: usize>(v: &[V], u: &[V]) -> f64 {
+    // Vectorised form of dot product: (1) compute dot across L lanes.
     let r = u
         .iter()
         .zip(v)
         .map(|(&x, &y)| x * y)
-        .fold(F64x2::splat(0.0), |s, x| s + x);
+        .fold(V::splat(0.0), |s, x| s + x);
 
-    // (2) sum the two lanes.
+    // (2) sum the L lanes.
     r.sum()
 }
 
 // Hint that this function should not be inlined. Keep the parallelised code tight, and vectorize
 // better.
 #[inline(never)]
-fn div_and_add(x: F64x2, a0: F64x2, a1: F64x2, s0: &mut F64x2, s1: &mut F64x2) {
-    *s0 = *s0 + x / a0;
-    *s1 = *s1 + x / a1;
+fn div_and_add<V: Simd<L>, const L: usize>(x: V, a: [V; L], s: &mut [V; L]) {
+    for (s, a) in s.iter_mut().zip(a) {
+        *s = *s + x / a;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths_agree() {
+        let n = 200;
+        let answers = [
+            spectralnorm::<f64, 1>(n, |_, _, _| {}),
+            spectralnorm::<F64x2, 2>(n, |_, _, _| {}),
+            spectralnorm::<F64x4, 4>(n, |_, _, _| {}),
+            spectralnorm::<F64x8, 8>(n, |_, _, _| {}),
+        ];
+        for answer in answers {
+            assert!((answer - answers[0]).abs() < 1e-12, "{:?}", answers);
+        }
+        assert_eq!(format!("{:.9}", answers[0]), "1.274223601");
+    }
 }
@@ -0,0 +1,38 @@
+//! Determinism dumps: the exact bit patterns of intermediate floating-point
+//! state, so that runtimes can be compared to the last bit instead of by the
+//! few digits a program prints.
+//!
+//! A dump has one record per line, a label identifying the state followed by
+//! the bits of each value as 16 hex digits:
+//!
+//! ```text
+//! step=100 body=0: 3f4a0b5ba6dd2dc6 3f2ab60fd1b6b2a4 ...
+//! ```
+//!
+//! In the repository root, `just dump-rust` dumps a program on every runtime
+//! and reports where each diverges first from native, and `dumpdiff` does the
+//! same for dumps at hand.
+
+use std::fmt;
+use std::io::{self, Write};
+
+/// Write the record `label: bits...` of `values`.
+pub fn record<W: Write>(out: &mut W, label: fmt::Arguments, values: &[f64]) -> io::Result<()> {
+    write!(out, "{}:", label)?;
+    for v in values.iter() {
+        write!(out, " {:016x}", v.to_bits())?;
+    }
+    writeln!(out)
+}
+
+/// Parse the `--dump K` interval of a program, which must be positive.
+pub fn interval(arg: Option<String>) -> io::Result<usize> {
+    let arg = arg.unwrap_or_default();
+    match arg.parse() {
+        Ok(k) if k > 0 => Ok(k),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid dump interval: {}", arg),
+        )),
+    }
+}
@@ -0,0 +1,97 @@
+//! Minimal host interface for building the programs for
+//! `wasm32-unknown-unknown`, where there is no WASI.
+//!
+//! The embedder supplies these functions in the `bench` import module:
+//!
+//! * `read_input(ptr, len) -> n` -- read at most `len` bytes of input, 0 at EOF
+//! * `write_output(ptr, len)` -- write `len` bytes of output
+//! * `arg(index, ptr, len) -> n` -- copy at most `len` bytes of argument
+//!   `index` and return its full length, or -1 if there is no such argument
+//!
+//! On every other target these are plain `std` stdin, stdout and arguments.
+
+use std::fmt;
+use std::io::Write;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::io::{stdin, stdout, Stdin, Stdout};
+
+/// Command-line arguments, including the program name.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn args() -> impl Iterator<Item = String> {
+    std::env::args()
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use self::shim::{args, stdin, stdout, Stdin, Stdout};
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod shim {
+    use std::io::{self, Read, Write};
+
+    #[link(wasm_import_module = "bench")]
+    extern "C" {
+        fn read_input(ptr: *mut u8, len: usize) -> usize;
+        fn write_output(ptr: *const u8, len: usize);
+        fn arg(index: usize, ptr: *mut u8, len: usize) -> isize;
+    }
+
+    pub struct Stdin;
+
+    pub fn stdin() -> Stdin {
+        Stdin
+    }
+
+    impl Read for Stdin {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            Ok(unsafe { read_input(buf.as_mut_ptr(), buf.len()) })
+        }
+    }
+
+    pub struct Stdout;
+
+    pub fn stdout() -> Stdout {
+        Stdout
+    }
+
+    impl Write for Stdout {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            unsafe { write_output(buf.as_ptr(), buf.len()) };
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Command-line arguments, including the program name.
+    pub fn args() -> impl Iterator<Item = String> {
+        (0..).map_while(|index| {
+            let len = unsafe { arg(index, std::ptr::null_mut(), 0) };
+            if len < 0 {
+                return None;
+            }
+            let mut buf = vec![0u8; len as usize];
+            unsafe { arg(index, buf.as_mut_ptr(), buf.len()) };
+            Some(String::from_utf8_lossy(&buf).into_owned())
+        })
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    stdout().write_fmt(args).expect("failed printing to stdout");
+}
+
+/// `println!` which writes through [`stdout`], so that the output reaches the
+/// embedder on `wasm32-unknown-unknown`. Import it to shadow `std::println!`.
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::host::_print(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::host::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
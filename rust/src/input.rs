@@ -0,0 +1,30 @@
+//! Reading benchmark inputs which may or may not be gzip-compressed.
+
+use flate2::read::MultiGzDecoder;
+use std::io::{self, ErrorKind, Read};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Wrap `inner` so that gzip-compressed input is decompressed on the fly.
+///
+/// The first two bytes are sniffed for the gzip magic number, plain input is
+/// passed through without any additional buffering.
+pub fn decompress<R: Read + 'static>(mut inner: R) -> io::Result<Box<dyn Read>> {
+    let mut magic = [0u8; 2];
+    let mut len = 0;
+    while len < magic.len() {
+        match inner.read(&mut magic[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let head = io::Cursor::new(magic).take(len as u64);
+    if magic == GZIP_MAGIC {
+        Ok(Box::new(MultiGzDecoder::new(head.chain(inner))))
+    } else {
+        Ok(Box::new(head.chain(inner)))
+    }
+}
@@ -0,0 +1,8 @@
+//! Helpers shared by the benchmark programs in `src/bin`.
+
+extern crate flate2;
+
+pub mod dump;
+pub mod host;
+pub mod input;
+pub mod memory;
@@ -0,0 +1,176 @@
+//! A wrapping global allocator which counts how often and by how much the
+//! memory of the program grows.
+//!
+//! In a wasm module that is the linear memory, which only grows with
+//! `memory.grow`, so the allocator compares `memory.size` before and after
+//! every allocation. Elsewhere it keeps track of the peak of the allocated
+//! bytes instead, also in 64 KiB wasm pages, as a heap that is never given
+//! back would grow.
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: Counting<System> = Counting(System);
+//! ```
+//!
+//! Nothing is counted until [`enable`] is called.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+
+/// Size of a wasm page.
+pub const PAGE_SIZE: usize = 65536;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static GROWS: AtomicUsize = AtomicUsize::new(0);
+static GROWN_PAGES: AtomicUsize = AtomicUsize::new(0);
+// negative when more is freed than was allocated since `enable`
+#[cfg(not(target_arch = "wasm32"))]
+static LIVE_BYTES: std::sync::atomic::AtomicIsize = std::sync::atomic::AtomicIsize::new(0);
+#[cfg(not(target_arch = "wasm32"))]
+static PEAK_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Counting<A>(pub A);
+
+/// How the memory grew since [`enable`].
+pub struct Stats {
+    /// Number of times the memory grew.
+    pub grows: usize,
+    /// Pages the memory grew by in total.
+    pub grown_pages: usize,
+    /// Size of the memory in pages.
+    pub pages: usize,
+}
+
+/// Start counting.
+pub fn enable() {
+    ENABLED.store(true, Relaxed);
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        grows: GROWS.load(Relaxed),
+        grown_pages: GROWN_PAGES.load(Relaxed),
+        pages: pages(),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn pages() -> usize {
+    core::arch::wasm32::memory_size(0)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pages() -> usize {
+    PEAK_PAGES.load(Relaxed)
+}
+
+fn record_growth(before: usize, after: usize) {
+    if after > before {
+        GROWS.fetch_add(1, Relaxed);
+        GROWN_PAGES.fetch_add(after - before, Relaxed);
+    }
+}
+
+impl<A> Counting<A> {
+    /// Count the growth of the memory by `f`, which allocates `new` bytes
+    /// and frees `old` bytes.
+    #[cfg(target_arch = "wasm32")]
+    #[inline(always)]
+    fn count<T>(&self, _new: usize, _old: usize, f: impl FnOnce() -> T) -> T {
+        let before = pages();
+        let result = f();
+        record_growth(before, pages());
+        result
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[inline(always)]
+    fn count<T>(&self, new: usize, old: usize, f: impl FnOnce() -> T) -> T {
+        // `old` is freed only after `new` is allocated, as by a moving realloc
+        let live = LIVE_BYTES.fetch_add(new as isize, Relaxed) + new as isize;
+        LIVE_BYTES.fetch_sub(old as isize, Relaxed);
+        let before = PEAK_PAGES.load(Relaxed);
+        let after = (live.max(0) as usize).div_ceil(PAGE_SIZE);
+        if after > before {
+            PEAK_PAGES.store(after, Relaxed);
+            record_growth(before, after);
+        }
+        f()
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !ENABLED.load(Relaxed) {
+            return self.0.alloc(layout);
+        }
+        self.count(layout.size(), 0, || self.0.alloc(layout))
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if !ENABLED.load(Relaxed) {
+            return self.0.alloc_zeroed(layout);
+        }
+        self.count(layout.size(), 0, || self.0.alloc_zeroed(layout))
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if ENABLED.load(Relaxed) {
+            self.count(0, layout.size(), || ());
+        }
+        self.0.dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !ENABLED.load(Relaxed) {
+            return self.0.realloc(ptr, layout, new_size);
+        }
+        self.count(new_size, layout.size(), || {
+            self.0.realloc(ptr, layout, new_size)
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::alloc::System;
+
+    #[test]
+    fn peak_of_live_bytes() {
+        let counting = Counting(System);
+        let pages = |n: usize| Layout::from_size_align(n * PAGE_SIZE, 8).unwrap();
+
+        unsafe {
+            // not counted before `enable`
+            let p = counting.alloc(pages(5));
+            counting.dealloc(p, pages(5));
+            assert_eq!(stats().pages, 0);
+
+            enable();
+            let a = counting.alloc(pages(3));
+            let s = stats();
+            assert_eq!((s.grows, s.grown_pages, s.pages), (1, 3, 3));
+
+            // freeing does not lower the peak, reusing the freed bytes does
+            // not raise it
+            counting.dealloc(a, pages(3));
+            assert_eq!(stats().pages, 3);
+            let b = counting.alloc(pages(2));
+            assert_eq!(stats().pages, 3);
+
+            // more live bytes than ever raise it, counting both blocks of a
+            // realloc
+            let c = counting.realloc(b, pages(2), 4 * PAGE_SIZE);
+            let s = stats();
+            assert_eq!((s.grows, s.grown_pages, s.pages), (2, 6, 6));
+
+            counting.dealloc(c, pages(4));
+            assert_eq!(stats().pages, 6);
+        }
+    }
+}
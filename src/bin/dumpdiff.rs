@@ -0,0 +1,43 @@
+//! Compare determinism dumps of a program (`--dump`) on several runtimes
+//! with the first one and report where each diverges first.
+//!
+//! ```sh
+//! $ cargo run --bin dumpdiff -- dumps/nbody-native.dump dumps/nbody-*.dump
+//! ```
+
+use anyhow::{bail, Context};
+use statistics::dump;
+
+fn load(path: &str) -> anyhow::Result<Vec<dump::Record>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+    dump::parse(&text).with_context(|| format!("{} is malformed", path))
+}
+
+fn main() -> anyhow::Result<()> {
+    let paths = std::env::args().skip(1).collect::<Vec<_>>();
+    if paths.len() < 2 {
+        bail!("usage: dumpdiff REFERENCE DUMP...");
+    }
+
+    let reference = load(&paths[0])?;
+    let mut diverged = false;
+    for path in paths[1..].iter().filter(|p| **p != paths[0]) {
+        match dump::first_divergence(&reference, &load(path)?) {
+            Some(divergence) => {
+                diverged = true;
+                println!("{}: {}", path, divergence);
+            }
+            None => println!(
+                "{}: identical to {} ({} records)",
+                path,
+                paths[0],
+                reference.len()
+            ),
+        }
+    }
+
+    if diverged {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,413 @@
+//! Builds the programs in `rust/` and benchmarks them with hyperfine on every
+//! available runtime, writing `results/<program>-<runtime>.json`.
+//!
+//! ```sh
+//! $ cargo run --release --bin runner -- [OPTIONS] [PROGRAM]...
+//!
+//!   --runtime NAME  run only on this runtime (repeatable); some runtimes,
+//!                   like `wasmtime-threads` and `wasmtime-host-shim`, run
+//!                   only if named
+//!   --arg ARGS      arguments of the program instead of the default one,
+//!                   separated by spaces
+//!   --input FILE    stdin of the program instead of the default one
+//!   --wasm FILE     run this prebuilt wasm module instead of building one
+//!   --tag VARIANT   store results as `<program>-<runtime>@<VARIANT>`, a
+//!                   build variant compared with the default build
+//!   --label NAME    store results as `<program>-<runtime>#<NAME>`, a run
+//!                   with other arguments, e.g. of a parameter sweep, which
+//!                   the reports leave out
+//!   --validate ULPS compare the output of every runtime with that of the
+//!                   first one (native unless left out), allowing numbers to
+//!                   differ by ULPS units in the last place; programs which
+//!                   round their numbers print every digit for it
+//!   --dump          instead of benchmarking, run every runtime once and
+//!                   report where its output, the `--dump` of the program
+//!                   given with `--arg`, diverges first from the first one
+//! ```
+//!
+//! Paths are relative to the repository root. Every result is also appended
+//! to `history.jsonl` with the time of the run, the git revision and the
+//! version of the runtime. With `--validate`, the verdict on the output is
+//! written to `results/<program>-<runtime>.validation.json`. With `--dump`,
+//! the dumps are written to `dumps/<program>-<runtime>.dump`.
+
+mod runtime;
+
+use anyhow::{anyhow, bail, Context};
+use runtime::{Artifact, Runtime};
+use statistics::dump;
+use statistics::history::{self, Record};
+use statistics::hyperfine;
+use statistics::ulps::{self, Validation};
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::Command;
+
+struct Program {
+    name: &'static str,
+    arg: &'static str,
+    input: &'static str,
+    /// Flag making the program print its numbers with every digit, which
+    /// `--validate` passes so that ulps are not hidden by rounding.
+    exact: Option<&'static str>,
+}
+
+static PROGRAMS: &[Program] = &[
+    Program {
+        name: "nbody",
+        arg: "50000000",
+        input: "/dev/null",
+        exact: Some("--exact"),
+    },
+    Program {
+        name: "fannkuchredux",
+        arg: "12",
+        input: "/dev/null",
+        exact: None,
+    },
+    Program {
+        name: "spectralnorm",
+        arg: "5500",
+        input: "/dev/null",
+        exact: Some("--exact"),
+    },
+    Program {
+        name: "mandelbrot",
+        arg: "16000",
+        input: "/dev/null",
+        exact: None,
+    },
+    Program {
+        name: "fasta",
+        arg: "25000000",
+        input: "/dev/null",
+        exact: None,
+    },
+    Program {
+        name: "revcomp",
+        arg: "25000000",
+        input: "input100000000.fasta",
+        exact: None,
+    },
+    Program {
+        name: "binarytrees",
+        arg: "21",
+        input: "/dev/null",
+        exact: None,
+    },
+    Program {
+        name: "knucleotide",
+        arg: "0",
+        input: "input25000000.fasta",
+        exact: None,
+    },
+    // not working
+    // Program { name: "pidigits", arg: "10000", input: "/dev/null" },
+    // Program { name: "regexredux", arg: "0", input: "input5000000.fasta" },
+];
+
+#[derive(Default)]
+struct Options {
+    runtimes: Vec<String>,
+    arg: Option<String>,
+    input: Option<String>,
+    wasm: Option<PathBuf>,
+    tag: Option<String>,
+    label: Option<String>,
+    validate: Option<u64>,
+    dump: bool,
+    programs: Vec<String>,
+}
+
+fn parse_args() -> anyhow::Result<Options> {
+    let mut opts = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--runtime" => opts.runtimes.push(value()?),
+            "--arg" => opts.arg = Some(value()?),
+            "--input" => opts.input = Some(value()?),
+            "--wasm" => opts.wasm = Some(value()?.into()),
+            "--tag" => opts.tag = Some(value()?),
+            "--label" => opts.label = Some(value()?),
+            "--validate" => {
+                let ulps = value()?;
+                opts.validate = Some(
+                    ulps.parse()
+                        .map_err(|_| anyhow!("invalid number of ulps: {}", ulps))?,
+                );
+            }
+            "--dump" => opts.dump = true,
+            _ if arg.starts_with("--") => bail!("unknown option: {}", arg),
+            _ => opts.programs.push(arg),
+        }
+    }
+
+    Ok(opts)
+}
+
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let status = command
+        .status()
+        .with_context(|| format!("failed to run {:?}", command))?;
+    if !status.success() {
+        bail!("{:?} failed with {}", command, status);
+    }
+    Ok(())
+}
+
+/// Run `command` once with `input` as stdin and return its output.
+fn output(command: &str, input: &str) -> anyhow::Result<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} < {}", command, quote(input)))
+        .output()
+        .with_context(|| format!("failed to run {}", command))?;
+    if !output.status.success() {
+        bail!("{} failed with {}", command, output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn build(program: &str, artifact: Artifact) -> anyhow::Result<PathBuf> {
+    match artifact {
+        Artifact::Native => {
+            run(Command::new("cargo").current_dir("rust").args([
+                "build",
+                "--release",
+                "--bin",
+                program,
+            ]))?;
+            Ok(PathBuf::from(format!("rust/target/release/{}", program)))
+        }
+        Artifact::Wasi => {
+            run(Command::new("cargo").current_dir("rust").args([
+                "wasi",
+                "build",
+                "--release",
+                "--bin",
+                program,
+            ]))?;
+            Ok(PathBuf::from(format!(
+                "rust/target/wasm32-wasi/release/{}.wasm",
+                program
+            )))
+        }
+        Artifact::WasiThreads => {
+            run(Command::new("cargo").current_dir("rust").args([
+                "build",
+                "--release",
+                "--target",
+                "wasm32-wasip1-threads",
+                "--bin",
+                program,
+            ]))?;
+            Ok(PathBuf::from(format!(
+                "rust/target/wasm32-wasip1-threads/release/{}.wasm",
+                program
+            )))
+        }
+        Artifact::Unknown => {
+            run(Command::new("cargo").current_dir("rust").args([
+                "build",
+                "--release",
+                "--target",
+                "wasm32-unknown-unknown",
+                "--bin",
+                program,
+            ]))?;
+            Ok(PathBuf::from(format!(
+                "rust/target/wasm32-unknown-unknown/release/{}.wasm",
+                program
+            )))
+        }
+    }
+}
+
+/// Quote `word` for the shell hyperfine runs the command with.
+fn quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=@+:".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let opts = parse_args()?;
+
+    let programs = if opts.programs.is_empty() {
+        PROGRAMS.iter().collect::<Vec<_>>()
+    } else {
+        opts.programs
+            .iter()
+            .map(|name| {
+                PROGRAMS
+                    .iter()
+                    .find(|p| p.name == name)
+                    .ok_or_else(|| anyhow!("unknown program: {}", name))
+            })
+            .collect::<anyhow::Result<_>>()?
+    };
+
+    let timestamp = history::now();
+    let revision = history::revision();
+
+    let mut runtimes = Vec::<(Box<dyn Runtime>, Option<String>)>::new();
+    for rt in runtime::all() {
+        let named = opts.runtimes.iter().any(|n| n == rt.name());
+        if !named && (rt.on_request() || !opts.runtimes.is_empty()) {
+            continue;
+        }
+        // a prebuilt module can only run on wasm runtimes
+        if opts.wasm.is_some() && rt.artifact() != Artifact::Wasi {
+            continue;
+        }
+        if !rt.available() {
+            eprintln!("note: skipping {}, it is not installed", rt.name());
+            continue;
+        }
+        let version = rt.version();
+        eprintln!(
+            "{}: {}",
+            rt.name(),
+            version.as_deref().unwrap_or("unknown version")
+        );
+        runtimes.push((rt, version));
+    }
+
+    for program in programs {
+        let args = opts
+            .arg
+            .as_deref()
+            .unwrap_or(program.arg)
+            .split_whitespace()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+        let input = opts.input.as_deref().unwrap_or(program.input);
+        let mut reference = None::<(&str, String)>;
+        let mut dumps = None::<(&str, Vec<dump::Record>)>;
+
+        let mut artifacts = Vec::<(Artifact, PathBuf)>::new();
+        for (rt, _) in runtimes.iter() {
+            if artifacts.iter().any(|(a, _)| *a == rt.artifact()) {
+                continue;
+            }
+            let path = match &opts.wasm {
+                Some(wasm) => wasm.clone(),
+                None => build(program.name, rt.artifact())?,
+            };
+            artifacts.push((rt.artifact(), path));
+        }
+
+        for (rt, version) in runtimes.iter() {
+            let artifact = &artifacts
+                .iter()
+                .find(|(a, _)| *a == rt.artifact())
+                .unwrap()
+                .1;
+            let command_of = |args: &[String]| {
+                rt.command(artifact, args)
+                    .iter()
+                    .map(|w| quote(w))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            let command = command_of(&args);
+
+            let mut name = format!("{}-{}", program.name, rt.name());
+            if let Some(tag) = &opts.tag {
+                name = format!("{}@{}", name, tag);
+            }
+            if let Some(label) = &opts.label {
+                name = format!("{}#{}", name, label);
+            }
+
+            if opts.dump {
+                let output = output(&command, input)?;
+                std::fs::create_dir_all("dumps")?;
+                std::fs::write(format!("dumps/{}.dump", name), &output)?;
+
+                let records = dump::parse(&output)
+                    .with_context(|| format!("malformed dump of {}", command))?;
+                match &dumps {
+                    None => dumps = Some((rt.name(), records)),
+                    Some((ref_name, ref_records)) => {
+                        match dump::first_divergence(ref_records, &records) {
+                            Some(d) => eprintln!("{} diverges from {}: {}", name, ref_name, d),
+                            None => eprintln!("{} is identical to {}", name, ref_name),
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let runs: &[&str] = if rt.interpreter() {
+                &["--runs", "3"]
+            } else {
+                &["-w", "3"]
+            };
+
+            // a runtime failing on a program, e.g. by running out of memory,
+            // should not cost the results of the runtimes after it
+            let benchmark = run(Command::new("hyperfine").args(runs).args([
+                "--export-json",
+                &format!("results/{}.json", name),
+                "--export-markdown",
+                &format!("results/{}.md", name),
+                &format!("{} < {} > /dev/null", command, quote(input)),
+            ]));
+            if let Err(e) = benchmark {
+                eprintln!("warning: skipping {}: {:#}", name, e);
+                continue;
+            }
+
+            if let Some(tolerance) = opts.validate {
+                let exact_args = program
+                    .exact
+                    .iter()
+                    .map(|flag| flag.to_string())
+                    .chain(args.iter().cloned())
+                    .collect::<Vec<_>>();
+                let output = output(&command_of(&exact_args), input)?;
+
+                let (ref_name, ref_output) = reference.get_or_insert((rt.name(), output.clone()));
+                let (max_ulps, mismatch) = ulps::compare(ref_output, &output, tolerance);
+                if let Some(m) = &mismatch {
+                    eprintln!(
+                        "warning: output of {} differs from {}: {}",
+                        name, ref_name, m
+                    );
+                }
+                let validation = Validation {
+                    reference: ref_name.to_string(),
+                    tolerance,
+                    max_ulps,
+                    mismatch,
+                };
+                serde_json::to_writer_pretty(
+                    File::create(format!("results/{}.validation.json", name))?,
+                    &validation,
+                )?;
+            }
+
+            let res = hyperfine::load(&format!("results/{}.json", name))?;
+            history::append(&Record {
+                timestamp,
+                revision: revision.clone(),
+                program: program.name.to_string(),
+                runtime: rt.name().to_string(),
+                variant: opts.tag.clone(),
+                label: opts.label.clone(),
+                version: version.clone(),
+                result: res,
+            })
+            .context("failed to append to history.jsonl")?;
+        }
+    }
+
+    Ok(())
+}
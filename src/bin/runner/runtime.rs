@@ -0,0 +1,324 @@
+use std::path::Path;
+use std::process::Command;
+
+/// What has to be built before a runtime can run a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Artifact {
+    /// `rust/target/release/<program>`
+    Native,
+    /// `rust/target/wasm32-wasi/release/<program>.wasm`
+    Wasi,
+    /// `rust/target/wasm32-wasip1-threads/release/<program>.wasm`
+    WasiThreads,
+    /// `rust/target/wasm32-unknown-unknown/release/<program>.wasm`, which
+    /// imports the host interface of `rust/src/host.rs` instead of WASI
+    Unknown,
+}
+
+pub trait Runtime {
+    /// Name used for the result files, e.g. `wasmer-llvm`.
+    fn name(&self) -> &str;
+
+    /// Version reported by the runtime, `None` if it is not installed.
+    fn version(&self) -> Option<String>;
+
+    fn artifact(&self) -> Artifact;
+
+    /// Command line running the built `artifact` with `args`.
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String>;
+
+    fn available(&self) -> bool {
+        self.version().is_some()
+    }
+
+    /// Interpreters are run fewer times, they are 100x slower or more.
+    fn interpreter(&self) -> bool {
+        false
+    }
+
+    /// Run only if named with `--runtime`, e.g. for a build that only some
+    /// programs make use of.
+    fn on_request(&self) -> bool {
+        false
+    }
+}
+
+/// First line of the output of `program args...`, if it succeeds.
+fn probe(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map(str::to_string)
+}
+
+fn command_line(program: &str, flags: &[&str], artifact: &Path, args: &[String]) -> Vec<String> {
+    std::iter::once(program.to_string())
+        .chain(flags.iter().map(|f| f.to_string()))
+        .chain(std::iter::once(artifact.display().to_string()))
+        .chain(args.iter().cloned())
+        .collect()
+}
+
+pub struct Native;
+
+impl Runtime for Native {
+    fn name(&self) -> &str {
+        "native"
+    }
+
+    fn version(&self) -> Option<String> {
+        probe("rustc", &["--version"])
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::Native
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        std::iter::once(artifact.display().to_string())
+            .chain(args.iter().cloned())
+            .collect()
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+}
+
+pub struct Wasmer {
+    name: String,
+    flag: String,
+}
+
+impl Wasmer {
+    /// `backend` is one of `llvm`, `cranelift` and `singlepass`.
+    pub fn new(backend: &str) -> Wasmer {
+        Wasmer {
+            name: format!("wasmer-{}", backend),
+            flag: format!("--{}", backend),
+        }
+    }
+}
+
+impl Runtime for Wasmer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> Option<String> {
+        probe("wasmer", &["--version"])
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::Wasi
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        command_line("wasmer", &[&self.flag], artifact, args)
+    }
+}
+
+pub struct Wasmtime;
+
+impl Runtime for Wasmtime {
+    fn name(&self) -> &str {
+        "wasmtime"
+    }
+
+    fn version(&self) -> Option<String> {
+        probe("wasmtime", &["--version"])
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::Wasi
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        // `-W` wasm and `-S` WASI options, as in wasmtime 41 which
+        // `harness/` embeds; `--enable-all` is gone
+        command_line(
+            "wasmtime",
+            &["run", "-W", "all-proposals=y"],
+            artifact,
+            args,
+        )
+    }
+}
+
+/// wasmtime with WASI threads, running the `wasm32-wasip1-threads` build.
+pub struct WasmtimeThreads;
+
+impl Runtime for WasmtimeThreads {
+    fn name(&self) -> &str {
+        "wasmtime-threads"
+    }
+
+    fn version(&self) -> Option<String> {
+        probe("wasmtime", &["--version"])
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::WasiThreads
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        command_line(
+            "wasmtime",
+            &["run", "-W", "all-proposals=y", "-S", "threads=y"],
+            artifact,
+            args,
+        )
+    }
+
+    fn on_request(&self) -> bool {
+        true
+    }
+}
+
+/// wasmtime embedded in `harness/`, which supplies the host interface instead
+/// of WASI. The harness has to be built first.
+pub struct HostShim;
+
+const HARNESS: &str = "harness/target/release/harness";
+
+impl Runtime for HostShim {
+    fn name(&self) -> &str {
+        "wasmtime-host-shim"
+    }
+
+    /// The version of wasmtime the harness is built with.
+    fn version(&self) -> Option<String> {
+        if !Path::new(HARNESS).exists() {
+            return None;
+        }
+        let lock = std::fs::read_to_string("harness/Cargo.lock").ok()?;
+        let mut lines = lock.lines();
+        lines.find(|l| *l == r#"name = "wasmtime""#)?;
+        let version = lines.next()?.strip_prefix("version = ")?;
+        Some(format!("wasmtime {} (harness)", version.trim_matches('"')))
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::Unknown
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        command_line(HARNESS, &[], artifact, args)
+    }
+
+    fn on_request(&self) -> bool {
+        true
+    }
+}
+
+/// WebAssembly Micro Runtime, `iwasm` built with its interpreter.
+pub struct Wamr;
+
+impl Wamr {
+    /// Explicit sizes, rather than the defaults of the installed `iwasm`,
+    /// which are too small for the deeper recursions and change between
+    /// releases: 1 MiB for the stack of the interpreter and 16 MiB for the
+    /// heap of the module instance.
+    const STACK_SIZE: &'static str = "--stack-size=1048576";
+    const HEAP_SIZE: &'static str = "--heap-size=16777216";
+}
+
+impl Runtime for Wamr {
+    fn name(&self) -> &str {
+        "wamr"
+    }
+
+    fn version(&self) -> Option<String> {
+        probe("iwasm", &["--version"])
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::Wasi
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        command_line(
+            "iwasm",
+            &[Self::STACK_SIZE, Self::HEAP_SIZE],
+            artifact,
+            args,
+        )
+    }
+
+    fn interpreter(&self) -> bool {
+        true
+    }
+}
+
+pub struct Wasm3;
+
+impl Runtime for Wasm3 {
+    fn name(&self) -> &str {
+        "wasm3"
+    }
+
+    fn version(&self) -> Option<String> {
+        probe("wasm3", &["--version"])
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::Wasi
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        command_line("wasm3", &[], artifact, args)
+    }
+
+    fn interpreter(&self) -> bool {
+        true
+    }
+}
+
+/// The `wasmi_cli` binary of the wasmi interpreter.
+pub struct Wasmi;
+
+impl Runtime for Wasmi {
+    fn name(&self) -> &str {
+        "wasmi"
+    }
+
+    fn version(&self) -> Option<String> {
+        probe("wasmi_cli", &["--version"])
+    }
+
+    fn artifact(&self) -> Artifact {
+        Artifact::Wasi
+    }
+
+    fn command(&self, artifact: &Path, args: &[String]) -> Vec<String> {
+        command_line("wasmi_cli", &[], artifact, args)
+    }
+
+    fn interpreter(&self) -> bool {
+        true
+    }
+}
+
+/// Every runtime the runner knows about, in the order they are run.
+pub fn all() -> Vec<Box<dyn Runtime>> {
+    vec![
+        Box::new(Native),
+        Box::new(Wasmer::new("llvm")),
+        Box::new(Wasmer::new("cranelift")),
+        Box::new(Wasmer::new("singlepass")),
+        Box::new(Wasmtime),
+        Box::new(WasmtimeThreads),
+        Box::new(HostShim),
+        Box::new(Wamr),
+        Box::new(Wasm3),
+        Box::new(Wasmi),
+    ]
+}
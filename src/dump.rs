@@ -0,0 +1,79 @@
+//! Determinism dumps written by the programs in `rust/` with `--dump` (see
+//! `rust/src/dump.rs`): the exact bit patterns of intermediate floating-point
+//! state, one `label: bits...` record per line.
+
+use anyhow::{anyhow, Context};
+
+use crate::ulps;
+
+pub struct Record {
+    pub label: String,
+    pub values: Vec<f64>,
+}
+
+/// Parse the records of a dump.
+pub fn parse(text: &str) -> anyhow::Result<Vec<Record>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let (label, values) = line
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("line {}: no `label:`", i + 1))?;
+            let values = values
+                .split_whitespace()
+                .map(|v| u64::from_str_radix(v, 16).map(f64::from_bits))
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("line {}: malformed bits", i + 1))?;
+            Ok(Record {
+                label: label.to_string(),
+                values,
+            })
+        })
+        .collect()
+}
+
+/// Where `dump` differs from `reference` first, if anywhere.
+pub fn first_divergence(reference: &[Record], dump: &[Record]) -> Option<String> {
+    for (i, (r, d)) in reference.iter().zip(dump).enumerate() {
+        if d.label != r.label {
+            return Some(format!(
+                "line {}: `{}` instead of `{}`",
+                i + 1,
+                d.label,
+                r.label
+            ));
+        }
+        if d.values.len() != r.values.len() {
+            return Some(format!(
+                "line {} (`{}`): {} values instead of {}",
+                i + 1,
+                r.label,
+                d.values.len(),
+                r.values.len()
+            ));
+        }
+        for (j, (&x, &y)) in r.values.iter().zip(&d.values).enumerate() {
+            if x.to_bits() != y.to_bits() {
+                return Some(format!(
+                    "line {} (`{}`), value {}: {:016x} ({:e}) instead of {:016x} ({:e}), {} ulps apart",
+                    i + 1,
+                    r.label,
+                    j,
+                    y.to_bits(),
+                    y,
+                    x.to_bits(),
+                    x,
+                    ulps::distance(x, y)
+                ));
+            }
+        }
+    }
+    if dump.len() != reference.len() {
+        return Some(format!(
+            "{} records instead of {}",
+            dump.len(),
+            reference.len()
+        ));
+    }
+    None
+}
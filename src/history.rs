@@ -0,0 +1,133 @@
+//! Append-only archive of every benchmark run in `history.jsonl`, one JSON
+//! record per line, so that results can be compared across months.
+
+use crate::hyperfine;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const PATH: &str = "history.jsonl";
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Record {
+    /// Start of the run in seconds since the Unix epoch, shared by every
+    /// record of the run.
+    pub timestamp: u64,
+    /// `git rev-parse HEAD` of this repository.
+    pub revision: String,
+    pub program: String,
+    pub runtime: String,
+    /// Build variant, `None` for the default build.
+    pub variant: Option<String>,
+    /// Label of a run with other arguments than the default ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Version reported by the runtime.
+    pub version: Option<String>,
+    #[serde(flatten)]
+    pub result: hyperfine::Result,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Current git revision, "unknown" outside of a git checkout.
+pub fn revision() -> String {
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+pub fn append(record: &Record) -> io::Result<()> {
+    let mut f = OpenOptions::new().create(true).append(true).open(PATH)?;
+    writeln!(&mut f, "{}", serde_json::to_string(record)?)
+}
+
+/// Every record, oldest first. A missing archive is empty. Lines which do
+/// not parse, like one cut short by an interrupted run, and records which are
+/// unusable are left out, recording why in `diagnostics`.
+pub fn load(diagnostics: &mut Vec<String>) -> anyhow::Result<Vec<Record>> {
+    let f = match File::open(PATH) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(parse(BufReader::new(f), PATH, diagnostics)?)
+}
+
+fn parse(
+    input: impl BufRead,
+    path: &str,
+    diagnostics: &mut Vec<String>,
+) -> io::Result<Vec<Record>> {
+    let mut records = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                diagnostics.push(format!("{}:{}: {}", path, i + 1, e));
+                continue;
+            }
+        };
+        let problems = record.result.validate(&record.program);
+        if !problems.is_empty() {
+            diagnostics.push(format!("{}:{}: {}", path, i + 1, problems.join("; ")));
+            continue;
+        }
+        records.push(record);
+    }
+    records.sort_by_key(|r| r.timestamp);
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(timestamp: u64, program: &str, times: &str) -> String {
+        format!(
+            r#"{{"timestamp": {}, "revision": "abc", "program": "{}", "runtime": "native",
+                "variant": null, "version": null, "command": "rust/target/release/{} 1000",
+                "mean": 1.0, "stddev": 0.0, "median": 1.0, "user": 0.5, "system": 0.0,
+                "min": 1.0, "max": 1.0, "times": {}}}"#,
+            timestamp, program, program, times
+        )
+        .replace('\n', "")
+    }
+
+    #[test]
+    fn bad_lines_are_left_out() {
+        let input = [
+            line(2, "nbody", "[1.0]"),
+            line(1, "fasta", "[1.0, 1.0]"),
+            // cut short by an interrupted run
+            line(3, "nbody", "[1.0]")[..40].to_string(),
+            line(4, "nbody", "[]"),
+            String::new(),
+        ]
+        .join("\n");
+
+        let mut diagnostics = vec![];
+        let records = parse(input.as_bytes(), PATH, &mut diagnostics).unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.timestamp).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+        assert!(diagnostics[0].starts_with("history.jsonl:3: EOF while parsing"));
+        assert_eq!(diagnostics[1], "history.jsonl:4: no run times");
+    }
+}
@@ -0,0 +1,256 @@
+//! The JSON written by `hyperfine --export-json`.
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Deserializer};
+use std::fs::File;
+use std::io::BufReader;
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Result {
+    pub command: String,
+    pub mean: f64,
+    /// 0 for a single run, for which hyperfine writes null.
+    #[serde(deserialize_with = "null_as_zero")]
+    pub stddev: f64,
+    pub median: f64,
+    pub user: f64,
+    pub system: f64,
+    pub min: f64,
+    pub max: f64,
+    pub times: Vec<f64>,
+}
+
+fn null_as_zero<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(d)?.unwrap_or(0.0))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Results {
+    pub results: Vec<Result>,
+}
+
+/// Read the first result of `path`.
+pub fn load(path: &str) -> anyhow::Result<Result> {
+    let f = File::open(path).with_context(|| format!("failed to open {}", path))?;
+    let res: Results = serde_json::from_reader(BufReader::new(f))
+        .with_context(|| format!("malformed {}", path))?;
+    res.results
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("{} has no results", path))
+}
+
+impl Result {
+    /// Problems which make the result unusable for `program`, empty if none.
+    pub fn validate(&self, program: &str) -> Vec<String> {
+        let mut problems = vec![];
+
+        if self.times.is_empty() {
+            problems.push("no run times".to_string());
+        }
+        if let Some(t) = self.times.iter().find(|t| !(t.is_finite() && **t > 0.0)) {
+            problems.push(format!("invalid run time {}", t));
+        }
+        for (name, v) in [("user", self.user), ("system", self.system)] {
+            if !(v.is_finite() && v >= 0.0) {
+                problems.push(format!("invalid {} time {}", name, v));
+            }
+        }
+
+        if !runs(&self.command, program) {
+            problems.push(format!(
+                "command `{}` does not run {}",
+                self.command, program
+            ));
+        }
+
+        problems
+    }
+
+    /// The result with mean, stddev, median, min and max recomputed from
+    /// `times`, and the fields which disagreed with them.
+    pub fn recompute(&self) -> (Result, Vec<String>) {
+        let res = Result {
+            mean: mean(&self.times),
+            stddev: stddev(&self.times),
+            median: median(&self.times),
+            min: self.times.iter().copied().fold(f64::INFINITY, f64::min),
+            max: self.times.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            ..self.clone()
+        };
+
+        let mut disagreements = vec![];
+        for (name, given, v) in [
+            ("mean", self.mean, res.mean),
+            ("stddev", self.stddev, res.stddev),
+            ("median", self.median, res.median),
+            ("min", self.min, res.min),
+            ("max", self.max, res.max),
+        ] {
+            let agree = (given.is_nan() && v.is_nan()) || (given - v).abs() <= 1e-6 * v.abs();
+            if !agree {
+                disagreements.push(format!(
+                    "{} is {} but {} from the run times",
+                    name, given, v
+                ));
+            }
+        }
+
+        (res, disagreements)
+    }
+}
+
+/// Whether `command` runs `program`: one of its words before the redirections
+/// is `program` or a path to it, with or without an extension, like in
+/// `java ... nbody`, `./nbody.gcc_run` or `wasmtime run .../nbody.wasm`.
+fn runs(command: &str, program: &str) -> bool {
+    command
+        .split_whitespace()
+        .take_while(|w| !w.starts_with(['<', '>']))
+        .map(|w| w.trim_matches(['\'', '"']))
+        .map(|w| w.rsplit('/').next().unwrap_or(w))
+        .any(|name| name.split('.').next() == Some(program))
+}
+
+pub fn mean(times: &[f64]) -> f64 {
+    times.iter().sum::<f64>() / times.len() as f64
+}
+
+/// Sample standard deviation, as hyperfine computes it, and 0 for a single
+/// run instead of none.
+pub fn stddev(times: &[f64]) -> f64 {
+    match times.len() {
+        0 => f64::NAN,
+        1 => 0.0,
+        n => {
+            let m = mean(times);
+            let ss = times.iter().map(|t| (t - m) * (t - m)).sum::<f64>();
+            (ss / (n - 1) as f64).sqrt()
+        }
+    }
+}
+
+/// The middle run time, or the mean of the two middle ones.
+pub fn median(times: &[f64]) -> f64 {
+    let mut t = times.to_vec();
+    t.sort_by(|a, b| a.total_cmp(b));
+    let n = t.len();
+    if n.is_multiple_of(2) {
+        (t[n / 2 - 1] + t[n / 2]) / 2.0
+    } else {
+        t[n / 2]
+    }
+}
+
+/// Mean without the `fraction` fastest and the `fraction` slowest run times,
+/// keeping at least the middle one or two, so that a half is the median.
+pub fn trimmed_mean(times: &[f64], fraction: f64) -> f64 {
+    let mut t = times.to_vec();
+    t.sort_by(|a, b| a.total_cmp(b));
+    let k = ((t.len() as f64 * fraction) as usize).min(t.len().saturating_sub(1) / 2);
+    mean(&t[k..t.len() - k])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(times: &[f64], stddev: &str) -> Result {
+        let json = format!(
+            r#"{{"command": "rust/target/release/nbody 1000", "mean": {}, "stddev": {},
+                "median": {}, "user": 0.1, "system": 0.0, "min": {}, "max": {},
+                "times": {:?}}}"#,
+            mean(times),
+            stddev,
+            median(times),
+            times.iter().copied().fold(f64::INFINITY, f64::min),
+            times.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            times
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn command_runs_program() {
+        for command in [
+            "rust/target/release/nbody 1000",
+            "cd rust && wasmtime run -W all-proposals=y target/wasm32-wasi/release/nbody.wasm 1000",
+            "'rust/target/variants/o3+lto/nbody.wasm' 1000 < /dev/null",
+            "./nbody.gcc_run 50000000 < /dev/null > /dev/null",
+            "java -cp java:/usr/share/java/fastutil.jar nbody 50000000",
+            "cd rust && cargo run --release --bin nbody 50000000",
+        ] {
+            assert!(runs(command, "nbody"), "{}", command);
+            assert!(!runs(command, "fasta"), "{}", command);
+        }
+
+        let knucleotide = "./knucleotide.gcc_run 0 < input25000000.fasta > /dev/null";
+        assert!(runs(knucleotide, "knucleotide"));
+        assert!(!runs(knucleotide, "fasta"));
+        assert!(!runs("rust/target/release/nbody-old 1000", "nbody"));
+        assert!(!runs("rust/target/release/spectralnorm 100", "spectral"));
+
+        let res = result(&[1.0], "null");
+        assert!(res.validate("nbody").is_empty());
+        assert_eq!(
+            res.validate("fannkuchredux"),
+            ["command `rust/target/release/nbody 1000` does not run fannkuchredux"]
+        );
+    }
+
+    #[test]
+    fn median_of_even_and_odd_counts() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert_eq!(median(&[1.0, 1.0]), 1.0);
+        assert_eq!(median(&[5.0]), 5.0);
+    }
+
+    #[test]
+    fn stddev_of_runs() {
+        let times = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(stddev(&times), (32.0f64 / 7.0).sqrt());
+        assert_eq!(stddev(&[1.5, 1.5]), 0.0);
+        assert_eq!(stddev(&[1.5]), 0.0);
+        assert!(stddev(&[]).is_nan());
+    }
+
+    #[test]
+    fn single_run_agrees_with_hyperfine() {
+        let res = result(&[1.5], "null");
+        assert_eq!(res.stddev, 0.0);
+        let (res, disagreements) = res.recompute();
+        assert_eq!(res.stddev, 0.0);
+        assert!(disagreements.is_empty(), "{:?}", disagreements);
+    }
+
+    #[test]
+    fn recompute_reports_disagreements() {
+        let times = [1.0, 2.0, 3.0, 4.0];
+        let (_, disagreements) = result(&times, &stddev(&times).to_string()).recompute();
+        assert!(disagreements.is_empty(), "{:?}", disagreements);
+
+        let mut res = result(&times, "null");
+        res.median = 3.0;
+        let (res, disagreements) = res.recompute();
+        assert_eq!(res.median, 2.5);
+        assert_eq!(
+            disagreements,
+            [
+                format!("stddev is 0 but {} from the run times", stddev(&times)),
+                "median is 3 but 2.5 from the run times".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn trimmed_mean_leaves_out_slow_and_fast_runs() {
+        let times = [1.0, 1.0, 1.1, 0.9, 1.0, 1.0, 0.1, 1.0, 1.0, 11.0];
+        assert_eq!(trimmed_mean(&times, 0.0), mean(&times));
+        assert_eq!(trimmed_mean(&times, 0.1), 1.0);
+        assert_eq!(trimmed_mean(&times, 0.25), 1.0);
+        assert_eq!(trimmed_mean(&times, 0.5), median(&times));
+        // fewer runs than a fraction of one are left as they are
+        assert_eq!(trimmed_mean(&times[..5], 0.1), mean(&times[..5]));
+    }
+}
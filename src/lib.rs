@@ -0,0 +1,7 @@
+//! Types shared by the aggregator (`src/main.rs`), the runner
+//! (`src/bin/runner/`) and `src/bin/dumpdiff.rs`.
+
+pub mod dump;
+pub mod history;
+pub mod hyperfine;
+pub mod ulps;
@@ -1,5 +1,13 @@
+mod plot;
+mod report;
+
+use report::{Central, Format, Report, Row, Statistic};
+use statistics::history;
+use statistics::hyperfine;
+use statistics::ulps::Validation;
 use std::io::Write;
-use std::{collections::BTreeMap, fs::File};
+use std::path::Path;
+use std::{collections::BTreeMap, fs, fs::File};
 
 static PROGRAMS: &[&str] = &[
     "nbody",
@@ -22,75 +30,428 @@ static IMPLS: &[&str] = &[
     "java",
 ];
 
-#[derive(Clone, Debug, serde::Deserialize)]
-struct Result {
-    command: String,
-    mean: f64,
-    stddev: f64,
-    median: f64,
-    user: f64,
-    system: f64,
-    min: f64,
-    max: f64,
-    times: Vec<f64>,
-}
-#[derive(Debug, serde::Deserialize)]
-struct Results {
-    results: Vec<Result>,
+/// Interpreters, which are optional and reported separately since they are
+/// orders of magnitude slower than the rest.
+static INTERPRETERS: &[&str] = &["wamr", "wasm3", "wasmi"];
+
+/// Implementations which run a wasm module and so have build variants.
+static WASM_IMPLS: &[&str] = &[
+    "wasmer-llvm",
+    "wasmer-cranelift",
+    "wasmer-singlepass",
+    "wasmtime",
+];
+
+/// Implementations whose summary of the ratios to rust-native exceeds this
+/// are left out of `lim.png`.
+const DEFAULT_OUTLIER_FACTOR: f64 = 2.0;
+
+/// Read and validate a result of `program`, recording why it is unusable in
+/// `diagnostics`. Its statistics are recomputed from the run times, and those
+/// which disagree are recorded too.
+fn load(path: &str, program: &str, diagnostics: &mut Vec<String>) -> Option<hyperfine::Result> {
+    let res = match hyperfine::load(path) {
+        Ok(res) => res,
+        Err(e) => {
+            diagnostics.push(format!("{:#}", e));
+            return None;
+        }
+    };
+
+    let problems = res.validate(program);
+    if !problems.is_empty() {
+        diagnostics.push(format!("{}: {}", path, problems.join("; ")));
+        return None;
+    }
+
+    let (res, disagreements) = res.recompute();
+    if !disagreements.is_empty() {
+        diagnostics.push(format!("{}: {}", path, disagreements.join("; ")));
+    }
+    Some(res)
 }
 
 fn main() -> anyhow::Result<()> {
+    let mut outlier_factor = DEFAULT_OUTLIER_FACTOR;
+    let mut formats = Vec::<Format>::new();
+    let mut statistic = Statistic::Geomean;
+    let mut central = Central::Mean;
+    let mut weights = BTreeMap::<String, f64>::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--outlier-factor" => {
+                outlier_factor = args
+                    .next()
+                    .and_then(|k| k.parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("--outlier-factor needs a number"))?
+            }
+            "--format" => formats.push(
+                args.next()
+                    .ok_or_else(|| anyhow::anyhow!("--format needs a value"))?
+                    .parse()?,
+            ),
+            "--statistic" => {
+                statistic = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--statistic needs a value"))?
+                    .parse()?
+            }
+            "--central" => {
+                central = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--central needs a value"))?
+                    .parse()?
+            }
+            "--weight" => {
+                let w = args.next().unwrap_or_default();
+                let (progn, w) = w
+                    .split_once('=')
+                    .and_then(|(p, w)| Some((p, w.parse::<f64>().ok()?)))
+                    .filter(|(_, w)| *w >= 0.0)
+                    .ok_or_else(|| anyhow::anyhow!("--weight needs PROGRAM=WEIGHT"))?;
+                if !PROGRAMS.contains(&progn) {
+                    anyhow::bail!("unknown program: {}", progn);
+                }
+                weights.insert(progn.to_string(), w);
+            }
+            _ => anyhow::bail!("unknown argument: {}", arg),
+        }
+    }
+
     let rename = vec![("native", "rust-native")]
         .into_iter()
         .collect::<BTreeMap<&str, &str>>();
-    let mut mm = BTreeMap::<String, Vec<f64>>::new();
+    let display = |imp: &str| rename.get(imp).copied().unwrap_or(imp).to_string();
+    let mut times = BTreeMap::<&str, Vec<(String, Vec<f64>)>>::new();
+    let mut default_build = BTreeMap::<(&str, &str), f64>::new();
+
+    let mut report = Report {
+        programs: PROGRAMS.iter().map(|p| p.to_string()).collect(),
+        implementations: IMPLS
+            .iter()
+            .chain(INTERPRETERS.iter())
+            .map(|imp| display(imp))
+            .collect(),
+        rows: vec![],
+        weights,
+        central,
+        diagnostics: vec![],
+    };
+    let mut diagnostics = vec![];
+
+    for &progn in PROGRAMS.iter() {
+        let mut native_time = None;
+
+        for &imp in IMPLS.iter().chain(INTERPRETERS.iter()) {
+            let path = format!("results/{}-{}.json", progn, imp);
+            if INTERPRETERS.contains(&imp) && !Path::new(&path).exists() {
+                eprintln!("note: no result of {} on {}", progn, imp);
+                continue;
+            }
+            let res = match load(&path, progn, &mut diagnostics) {
+                Some(res) => res,
+                None => continue,
+            };
+            let time = central.of(&res.times);
+
+            let vpath = format!("results/{}-{}.validation.json", progn, imp);
+            let mut validation = Validation::load(&vpath).unwrap_or_else(|e| {
+                diagnostics.push(format!("malformed {}: {:#}", vpath, e));
+                None
+            });
+            if let Some(v) = validation.as_mut() {
+                v.reference = display(&v.reference);
+                if let Some(m) = &v.mismatch {
+                    diagnostics.push(format!(
+                        "{}: output differs from {}: {}",
+                        vpath, v.reference, m
+                    ));
+                }
+            }
+
+            if imp == "native" {
+                native_time = Some(time);
+            }
+            let native_time = match native_time {
+                Some(t) => t,
+                None => {
+                    diagnostics.push(format!(
+                        "{}: no valid rust-native result of {} to compare with",
+                        path, progn
+                    ));
+                    continue;
+                }
+            };
+
+            report.rows.push(Row {
+                program: progn.to_string(),
+                implementation: display(imp),
+                mean: res.mean,
+                stddev: res.stddev,
+                median: res.median,
+                min: res.min,
+                max: res.max,
+                ratio: time / native_time,
+                user: res.user,
+                system: res.system,
+                validation,
+            });
+            times
+                .entry(progn)
+                .or_default()
+                .push((display(imp), res.times));
+            if IMPLS.contains(&imp) {
+                default_build.insert((progn, imp), time);
+            }
+        }
+    }
+
+    report.write_dat(&mut File::create("info.dat")?)?;
+
+    // e.g. "geometric mean of the ratios to rust-native (weights knucleotide=2)"
+    let describe = |what: &str| {
+        let weights = report.describe_weights();
+        if weights.is_empty() {
+            format!("{} of {}", statistic.describe(), what)
+        } else {
+            format!("{} of {} (weights {})", statistic.describe(), what, weights)
+        }
+    };
+    let ratio_label = format!("{} time relative to rust-native", central.describe());
+    let summary = |imp: &str| report.summary(&display(imp), statistic);
 
     {
-        let mut f = File::create("info.dat")?;
+        let keys = report::STATISTICS
+            .iter()
+            .map(|s| s.key().to_string())
+            .collect::<Vec<_>>();
 
-        writeln!(
-            &mut f,
-            "Program {}",
-            IMPLS
+        let impls = IMPLS.iter().map(|imp| display(imp)).collect::<Vec<_>>();
+        report.write_summary(&mut File::create("summary.dat")?, &impls)?;
+
+        plot::Chart {
+            output: "gm.png".to_string(),
+            data: "summary.dat".to_string(),
+            ylabel: describe("the ratios to rust-native"),
+            columns: vec![statistic.key().to_string()],
+            left_out: vec![],
+            log_scale: false,
+        }
+        .write("plot-gm.txt", &keys)?;
+
+        // only over the programs an interpreter has results for
+        let interpreters = INTERPRETERS
+            .iter()
+            .map(|imp| imp.to_string())
+            .collect::<Vec<_>>();
+        report.write_summary(&mut File::create("summary-interp.dat")?, &interpreters)?;
+
+        plot::Chart {
+            output: "gm-interp.png".to_string(),
+            data: "summary-interp.dat".to_string(),
+            ylabel: describe("the ratios to rust-native"),
+            columns: vec![statistic.key().to_string()],
+            left_out: vec![],
+            log_scale: true,
+        }
+        .write("plot-gm-interp.txt", &keys)?;
+    }
+
+    {
+        let header = IMPLS
+            .iter()
+            .chain(INTERPRETERS.iter())
+            .map(|imp| display(imp))
+            .collect::<Vec<_>>();
+        let interpreters = INTERPRETERS
+            .iter()
+            .filter(|imp| !summary(imp).is_nan())
+            .collect::<Vec<_>>();
+        let (fast, outliers): (Vec<&str>, Vec<&str>) = IMPLS
+            .iter()
+            .partition(|imp| !report.is_outlier(&display(imp), statistic, outlier_factor));
+
+        let interpreters_left_out = interpreters
+            .iter()
+            .map(|imp| format!("{} (interpreter)", imp))
+            .collect::<Vec<_>>();
+
+        plot::Chart {
+            output: "all.png".to_string(),
+            data: "info.dat".to_string(),
+            ylabel: ratio_label.clone(),
+            columns: IMPLS.iter().map(|imp| display(imp)).collect(),
+            left_out: interpreters_left_out.clone(),
+            log_scale: false,
+        }
+        .write("plot.txt", &header)?;
+
+        plot::Chart {
+            output: "lim.png".to_string(),
+            data: "info.dat".to_string(),
+            ylabel: ratio_label.clone(),
+            columns: fast.iter().map(|imp| display(imp)).collect(),
+            left_out: outliers
                 .iter()
-                .map(|imp| rename.get(imp).map(|r| *r).unwrap_or(imp).to_string())
-                .collect::<Vec<_>>()
-                .join(" ")
-        )?;
+                .map(|imp| {
+                    format!(
+                        "{} ({} {:.2}x > {}x rust-native)",
+                        display(imp),
+                        statistic.describe(),
+                        summary(imp),
+                        outlier_factor
+                    )
+                })
+                .chain(interpreters_left_out)
+                .collect(),
+            log_scale: false,
+        }
+        .write("plot-lim.txt", &header)?;
 
-        for &progn in PROGRAMS.iter() {
-            write!(&mut f, "{}", progn)?;
+        plot::Chart {
+            output: "log.png".to_string(),
+            data: "info.dat".to_string(),
+            ylabel: ratio_label.clone(),
+            columns: IMPLS
+                .iter()
+                .chain(interpreters)
+                .map(|imp| display(imp))
+                .collect(),
+            left_out: vec![],
+            log_scale: true,
+        }
+        .write("plot-log.txt", &header)?;
+    }
 
-            let mut native_time = 0.0;
+    fs::create_dir_all("detail")?;
+    for (progn, times) in times.iter() {
+        plot::write_detail(progn, times)?;
+    }
 
-            for &imp in IMPLS.iter() {
-                let path = format!("results/{}-{}.json", progn, imp);
-                let res: Results = serde_json::from_reader(File::open(path)?)?;
-                let res = res.results[0].clone();
+    fs::create_dir_all("cpu")?;
+    for progn in report.programs.iter() {
+        report.write_breakdown(&mut File::create(format!("cpu/{}.dat", progn))?, progn)?;
+        plot::write_breakdown(progn)?;
+    }
 
-                if imp == "native" {
-                    native_time = res.mean;
-                }
+    {
+        // results/<program>-<runtime>@<variant>.json, compared with the
+        // default build of the same program on the same runtime. Labelled
+        // runs (`#<label>`) have other arguments and are left out.
+        let mut variants = BTreeMap::<String, BTreeMap<&str, Vec<(f64, f64)>>>::new();
 
-                write!(&mut f, " {}", res.mean / native_time)?;
-                mm.entry(imp.to_string()).or_default().push(res.mean);
+        for entry in fs::read_dir("results")? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".validation.json") || name.contains('#') {
+                continue;
             }
+            let (name, variant) = match name.strip_suffix(".json").and_then(|n| n.split_once('@')) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            for &progn in PROGRAMS.iter() {
+                for &imp in WASM_IMPLS.iter() {
+                    if name != format!("{}-{}", progn, imp) {
+                        continue;
+                    }
 
+                    let path = format!("results/{}-{}@{}.json", progn, imp, variant);
+                    let res = match load(&path, progn, &mut diagnostics) {
+                        Some(res) => res,
+                        None => continue,
+                    };
+                    let default_time = match default_build.get(&(progn, imp)) {
+                        Some(t) => *t,
+                        None => {
+                            diagnostics.push(format!(
+                                "{}: no valid default build of {} on {} to compare with",
+                                path, progn, imp
+                            ));
+                            continue;
+                        }
+                    };
+
+                    variants
+                        .entry(variant.to_string())
+                        .or_default()
+                        .entry(imp)
+                        .or_default()
+                        .push((central.of(&res.times) / default_time, report.weight(progn)));
+                }
+            }
+        }
+
+        let mut f = File::create("variants.dat")?;
+        writeln!(&mut f, "Variant {}", WASM_IMPLS.join(" "))?;
+
+        for (variant, r) in variants.iter() {
+            write!(&mut f, "{}", variant)?;
+            for &imp in WASM_IMPLS.iter() {
+                match r.get(imp) {
+                    Some(r) => write!(&mut f, " {}", statistic.of(r))?,
+                    None => write!(&mut f, " NaN")?,
+                }
+            }
             writeln!(&mut f)?;
         }
+
+        let wasm_impls = WASM_IMPLS
+            .iter()
+            .map(|imp| imp.to_string())
+            .collect::<Vec<_>>();
+        plot::Chart {
+            output: "variants.png".to_string(),
+            data: "variants.dat".to_string(),
+            ylabel: describe("the time relative to the default build"),
+            columns: wasm_impls.clone(),
+            left_out: vec![],
+            log_scale: false,
+        }
+        .write("plot-variants.txt", &wasm_impls)?;
     }
 
     {
-        let mut f = File::create("geomean.dat")?;
-        writeln!(&mut f, "Language Geometric-mean")?;
-
-        for &imp in IMPLS.iter() {
-            let r = &mm[imp];
-            let gm = r.iter().product::<f64>().powf(1.0 / r.len() as f64);
-            let imp = rename.get(imp).map(|r| *r).unwrap_or(imp).to_string();
-            writeln!(&mut f, "{} {}", imp, gm)?;
+        // history.jsonl: the ratio of every default build to the latest
+        // native result of the same program at that time
+        let mut native = BTreeMap::<String, f64>::new();
+        let mut trends = BTreeMap::<String, BTreeMap<String, Vec<(u64, f64)>>>::new();
+
+        for r in history::load(&mut diagnostics)? {
+            if r.variant.is_some() || r.label.is_some() {
+                continue;
+            }
+            if r.runtime == "native" {
+                native.insert(r.program.clone(), central.of(&r.result.times));
+                continue;
+            }
+            if let Some(native_time) = native.get(&r.program) {
+                trends
+                    .entry(r.program.clone())
+                    .or_default()
+                    .entry(display(&r.runtime))
+                    .or_default()
+                    .push((r.timestamp, central.of(&r.result.times) / native_time));
+            }
+        }
+
+        if !trends.is_empty() {
+            fs::create_dir_all("trend")?;
+        }
+        for (progn, series) in trends.iter() {
+            plot::write_trend(progn, series)?;
         }
     }
 
+    for d in diagnostics.iter() {
+        eprintln!("warning: {}", d);
+    }
+    report.diagnostics = diagnostics;
+    for &format in formats.iter() {
+        report.write(format)?;
+    }
+
     Ok(())
 }
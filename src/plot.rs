@@ -0,0 +1,180 @@
+//! gnuplot scripts for the histograms of `info.dat` and the summaries,
+//! generated from the data instead of picking its columns by hand.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+
+pub struct Chart {
+    /// Name of the png file.
+    pub output: String,
+    /// Data file, whose first line names the columns.
+    pub data: String,
+    /// What is plotted, including the statistic it is summarized with.
+    pub ylabel: String,
+    /// Names of the columns to plot, as in the header of `data`.
+    pub columns: Vec<String>,
+    /// Implementations which have data but are left out, with the reason.
+    pub left_out: Vec<String>,
+    pub log_scale: bool,
+}
+
+impl Chart {
+    /// Write the script plotting `columns`, `header` being the column names
+    /// of `data` after the first one.
+    pub fn write(&self, path: &str, header: &[String]) -> io::Result<()> {
+        let mut f = File::create(path)?;
+
+        writeln!(&mut f, "set terminal png enhanced size 1200,800")?;
+        writeln!(&mut f, "set output '{}'", self.output)?;
+        writeln!(&mut f)?;
+        writeln!(&mut f, "set colorsequence classic")?;
+        writeln!(&mut f)?;
+        writeln!(&mut f, "set style data histogram")?;
+        writeln!(&mut f, "set style histogram cluster gap 1")?;
+        writeln!(&mut f)?;
+        writeln!(&mut f, "set style fill solid border rgb \"black\"")?;
+        writeln!(&mut f, "set auto x")?;
+        if self.log_scale {
+            writeln!(&mut f, "set logscale y")?;
+            writeln!(&mut f, "set yrange [*:*]")?;
+        } else {
+            writeln!(&mut f, "set yrange [0:*]")?;
+        }
+        writeln!(&mut f, "set ylabel \"{}\"", self.ylabel)?;
+        if !self.left_out.is_empty() {
+            writeln!(
+                &mut f,
+                "set title \"Not shown: {}\"",
+                self.left_out.join(", ")
+            )?;
+        }
+        writeln!(&mut f)?;
+
+        let plots = self
+            .columns
+            .iter()
+            .map(|c| {
+                let i = header.iter().position(|h| h == c).unwrap() + 2;
+                format!("using {}:xtic(1) title col", i)
+            })
+            .collect::<Vec<_>>();
+        writeln!(
+            &mut f,
+            "plot '{}' {}",
+            self.data,
+            plots.join(", \\\n        '' ")
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Write `detail/<program>.dat` with every run time of every implementation,
+/// and the script drawing them as one box plot per implementation.
+pub fn write_detail(program: &str, times: &[(String, Vec<f64>)]) -> io::Result<()> {
+    let data = format!("detail/{}.dat", program);
+
+    {
+        let mut f = File::create(&data)?;
+        writeln!(&mut f, "# implementation time")?;
+        for (imp, times) in times.iter() {
+            for t in times.iter() {
+                writeln!(&mut f, "{} {}", imp, t)?;
+            }
+        }
+    }
+
+    let mut f = File::create(format!("detail/{}.txt", program))?;
+
+    writeln!(&mut f, "set terminal png enhanced size 1200,800")?;
+    writeln!(&mut f, "set output 'detail/{}.png'", program)?;
+    writeln!(&mut f)?;
+    writeln!(&mut f, "set title \"{}\"", program)?;
+    writeln!(&mut f, "set style data boxplot")?;
+    writeln!(&mut f, "set style boxplot outliers pointtype 7")?;
+    writeln!(&mut f, "set style fill solid 0.25 border -1")?;
+    writeln!(&mut f, "set boxwidth 0.5")?;
+    writeln!(&mut f, "set pointsize 0.5")?;
+    writeln!(&mut f, "set logscale y")?;
+    writeln!(&mut f, "set ylabel \"time [s]\"")?;
+    writeln!(&mut f, "unset key")?;
+    writeln!(&mut f)?;
+    writeln!(&mut f, "plot '{}' using (1.0):2:(0.5):1", data)?;
+
+    Ok(())
+}
+
+/// Write `trend/<program>.dat` with the ratio to rust-native of every
+/// implementation over time, one data block per implementation, and the
+/// script drawing them as lines.
+pub fn write_trend(program: &str, series: &BTreeMap<String, Vec<(u64, f64)>>) -> io::Result<()> {
+    let data = format!("trend/{}.dat", program);
+
+    {
+        let mut f = File::create(&data)?;
+        writeln!(&mut f, "# timestamp ratio")?;
+        for (imp, points) in series.iter() {
+            writeln!(&mut f, "# {}", imp)?;
+            for (t, r) in points.iter() {
+                writeln!(&mut f, "{} {}", t, r)?;
+            }
+            writeln!(&mut f)?;
+            writeln!(&mut f)?;
+        }
+    }
+
+    let mut f = File::create(format!("trend/{}.txt", program))?;
+
+    writeln!(&mut f, "set terminal png enhanced size 1200,800")?;
+    writeln!(&mut f, "set output 'trend/{}.png'", program)?;
+    writeln!(&mut f)?;
+    writeln!(&mut f, "set title \"{}\"", program)?;
+    writeln!(&mut f, "set xdata time")?;
+    writeln!(&mut f, "set timefmt \"%s\"")?;
+    writeln!(&mut f, "set format x \"%Y-%m-%d\"")?;
+    writeln!(&mut f, "set xtics rotate")?;
+    writeln!(&mut f, "set ylabel \"ratio to rust-native\"")?;
+    writeln!(&mut f, "set key outside right")?;
+    writeln!(&mut f)?;
+
+    let plots = series
+        .keys()
+        .enumerate()
+        .map(|(i, imp)| format!("index {} using 1:2 with linespoints title \"{}\"", i, imp))
+        .collect::<Vec<_>>();
+    writeln!(&mut f, "plot '{}' {}", data, plots.join(", \\\n     '' "))?;
+
+    Ok(())
+}
+
+/// Write the script drawing `cpu/<program>.dat` as one stacked bar of user,
+/// system and idle fractions per implementation, so that time spent in the
+/// kernel (e.g. the I/O of a WASI runtime) stands out from computation.
+pub fn write_breakdown(program: &str) -> io::Result<()> {
+    let mut f = File::create(format!("cpu/{}.txt", program))?;
+
+    writeln!(&mut f, "set terminal png enhanced size 1200,800")?;
+    writeln!(&mut f, "set output 'cpu/{}.png'", program)?;
+    writeln!(&mut f)?;
+    writeln!(&mut f, "set title \"{}\"", program)?;
+    writeln!(&mut f, "set style data histogram")?;
+    writeln!(&mut f, "set style histogram rowstacked")?;
+    writeln!(&mut f, "set style fill solid border rgb \"black\"")?;
+    writeln!(&mut f, "set boxwidth 0.75")?;
+    writeln!(&mut f, "set yrange [0:1]")?;
+    writeln!(
+        &mut f,
+        "set ylabel \"fraction of the mean wall-clock time\""
+    )?;
+    writeln!(&mut f, "set xtics rotate")?;
+    writeln!(&mut f, "set key outside right")?;
+    writeln!(&mut f)?;
+    writeln!(
+        &mut f,
+        "plot 'cpu/{}.dat' using 2:xtic(1) title col, \\\n        '' using 3 title col, \\\n        '' using 4 title col",
+        program
+    )?;
+
+    Ok(())
+}
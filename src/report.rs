@@ -0,0 +1,636 @@
+//! The results as one table of (program, implementation) rows, from which
+//! `info.dat` and the `--format` outputs are written.
+
+use statistics::hyperfine;
+use statistics::ulps::Validation;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Which statistic of the run times of one result the ratios are taken of.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Central {
+    #[default]
+    Mean,
+    Median,
+    Min,
+    /// Mean without this fraction of the fastest and of the slowest runs.
+    Trimmed(f64),
+}
+
+impl FromStr for Central {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Central> {
+        match s {
+            "mean" => Ok(Central::Mean),
+            "median" => Ok(Central::Median),
+            "min" => Ok(Central::Min),
+            "trimmed" => Ok(Central::Trimmed(0.1)),
+            _ => s
+                .strip_prefix("trimmed:")
+                .and_then(|f| f.parse::<f64>().ok())
+                .filter(|f| (0.0..0.5).contains(f))
+                .map(Central::Trimmed)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "unknown central statistic: {} (mean, median, min or trimmed[:FRACTION])",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+impl Central {
+    pub fn of(self, times: &[f64]) -> f64 {
+        match self {
+            Central::Mean => hyperfine::mean(times),
+            Central::Median => hyperfine::median(times),
+            Central::Min => times.iter().copied().fold(f64::INFINITY, f64::min),
+            Central::Trimmed(f) => hyperfine::trimmed_mean(times, f),
+        }
+    }
+
+    pub fn describe(self) -> String {
+        match self {
+            Central::Mean => "mean".to_string(),
+            Central::Median => "median".to_string(),
+            Central::Min => "minimum".to_string(),
+            Central::Trimmed(f) => format!("{}% trimmed mean", f * 100.0),
+        }
+    }
+}
+
+/// How the ratios of an implementation over the programs are summarized into
+/// one number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Statistic {
+    Geomean,
+    Harmonic,
+    Median,
+}
+
+pub const STATISTICS: &[Statistic] = &[Statistic::Geomean, Statistic::Harmonic, Statistic::Median];
+
+impl FromStr for Statistic {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Statistic> {
+        match s {
+            "geomean" => Ok(Statistic::Geomean),
+            "harmonic" => Ok(Statistic::Harmonic),
+            "median" => Ok(Statistic::Median),
+            _ => anyhow::bail!("unknown statistic: {} (geomean, harmonic or median)", s),
+        }
+    }
+}
+
+impl Statistic {
+    /// Column name in the `.dat` files.
+    pub fn key(self) -> &'static str {
+        match self {
+            Statistic::Geomean => "Geometric-mean",
+            Statistic::Harmonic => "Harmonic-mean",
+            Statistic::Median => "Median",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Statistic::Geomean => "geometric mean",
+            Statistic::Harmonic => "harmonic mean",
+            Statistic::Median => "median",
+        }
+    }
+
+    /// Weighted summary of `(value, weight)` pairs, NaN if there are none.
+    /// Values of weight zero are left out.
+    pub fn of(self, v: &[(f64, f64)]) -> f64 {
+        let v = v
+            .iter()
+            .copied()
+            .filter(|&(_, w)| w > 0.0)
+            .collect::<Vec<_>>();
+        let total = v.iter().map(|(_, w)| w).sum::<f64>();
+        if v.is_empty() {
+            return f64::NAN;
+        }
+
+        match self {
+            Statistic::Geomean => (v.iter().map(|(x, w)| w * x.ln()).sum::<f64>() / total).exp(),
+            Statistic::Harmonic => total / v.iter().map(|(x, w)| w / x).sum::<f64>(),
+            Statistic::Median => {
+                // the smallest value with at least half of the weight at or below it
+                let mut v = v;
+                v.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let mut acc = 0.0;
+                for (x, w) in v.iter() {
+                    acc += w;
+                    if acc >= total / 2.0 {
+                        return *x;
+                    }
+                }
+                v[v.len() - 1].0
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Row {
+    pub program: String,
+    pub implementation: String,
+    pub mean: f64,
+    pub stddev: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    /// The central statistic of the report relative to rust-native on the
+    /// same program.
+    pub ratio: f64,
+    /// Mean CPU time in user and kernel mode.
+    pub user: f64,
+    pub system: f64,
+    /// Output compared with another runtime, if the runner was asked to.
+    pub validation: Option<Validation>,
+}
+
+impl Row {
+    /// Fractions of the wall-clock time spent in user mode, in kernel mode
+    /// and waiting. Multi-threaded programs can use more CPU than wall-clock
+    /// time, and then the fractions are of the CPU time without idle time.
+    pub fn breakdown(&self) -> (f64, f64, f64) {
+        let total = self.mean.max(self.user + self.system);
+        (
+            self.user / total,
+            self.system / total,
+            ((total - self.user - self.system) / total).max(0.0),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+    Markdown,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Format> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "markdown" | "md" => Ok(Format::Markdown),
+            _ => anyhow::bail!("unknown format: {} (csv, json or markdown)", s),
+        }
+    }
+}
+
+impl Format {
+    pub fn path(self) -> &'static str {
+        match self {
+            Format::Csv => "report.csv",
+            Format::Json => "report.json",
+            Format::Markdown => "report.md",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Row labels, in the order of the charts.
+    pub programs: Vec<String>,
+    /// Column labels, in the order of the charts.
+    pub implementations: Vec<String>,
+    pub rows: Vec<Row>,
+    /// Weight of each program in the summaries, 1 if not given.
+    pub weights: BTreeMap<String, f64>,
+    /// What `Row::ratio` is a ratio of.
+    pub central: Central,
+    /// Problems found in the result files.
+    pub diagnostics: Vec<String>,
+}
+
+impl Report {
+    pub fn get(&self, program: &str, implementation: &str) -> Option<&Row> {
+        self.rows
+            .iter()
+            .find(|r| r.program == program && r.implementation == implementation)
+    }
+
+    pub fn weight(&self, program: &str) -> f64 {
+        self.weights.get(program).copied().unwrap_or(1.0)
+    }
+
+    /// `(ratio, weight)` of `implementation` on every program it has results for.
+    pub fn ratios(&self, implementation: &str) -> Vec<(f64, f64)> {
+        self.rows
+            .iter()
+            .filter(|r| r.implementation == implementation)
+            .map(|r| (r.ratio, self.weight(&r.program)))
+            .collect()
+    }
+
+    pub fn summary(&self, implementation: &str, statistic: Statistic) -> f64 {
+        statistic.of(&self.ratios(implementation))
+    }
+
+    /// Whether the summary of the ratios of `implementation` exceeds
+    /// `factor`, which leaves it out of `lim.png`. Not if it has no results.
+    pub fn is_outlier(&self, implementation: &str, statistic: Statistic, factor: f64) -> bool {
+        self.summary(implementation, statistic) > factor
+    }
+
+    /// "knucleotide=2, revcomp=0.5", empty if every program has weight 1.
+    pub fn describe_weights(&self) -> String {
+        self.weights
+            .iter()
+            .filter(|(_, &w)| w != 1.0)
+            .map(|(p, w)| format!("{}={}", p, w))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// User, system and idle fractions of every implementation on `program`.
+    pub fn write_breakdown(&self, f: &mut impl Write, program: &str) -> io::Result<()> {
+        writeln!(f, "Implementation User System Idle")?;
+        for r in self.rows.iter().filter(|r| r.program == program) {
+            let (user, system, idle) = r.breakdown();
+            writeln!(f, "{} {} {} {}", r.implementation, user, system, idle)?;
+        }
+        Ok(())
+    }
+
+    /// Every statistic of the ratios of `implementations`, for gnuplot.
+    pub fn write_summary(&self, f: &mut impl Write, implementations: &[String]) -> io::Result<()> {
+        let keys = STATISTICS.iter().map(|s| s.key()).collect::<Vec<_>>();
+        writeln!(f, "Implementation {}", keys.join(" "))?;
+        for imp in implementations.iter() {
+            write!(f, "{}", imp)?;
+            for &stat in STATISTICS.iter() {
+                write!(f, " {}", self.summary(imp, stat))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, format: Format) -> anyhow::Result<()> {
+        let mut f = File::create(format.path())?;
+        match format {
+            Format::Csv => self.write_csv(&mut f)?,
+            Format::Json => self.write_json(&mut f)?,
+            Format::Markdown => self.write_markdown(&mut f)?,
+        }
+        Ok(())
+    }
+
+    /// Ratio matrix for gnuplot, NaN where there is no result.
+    pub fn write_dat(&self, f: &mut impl Write) -> io::Result<()> {
+        writeln!(f, "Program {}", self.implementations.join(" "))?;
+        for progn in self.programs.iter() {
+            write!(f, "{}", progn)?;
+            for imp in self.implementations.iter() {
+                match self.get(progn, imp) {
+                    Some(r) => write!(f, " {}", r.ratio)?,
+                    None => write!(f, " NaN")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    fn write_csv(&self, f: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            f,
+            "program,implementation,mean,stddev,median,min,max,ratio,user,system,max_ulps,valid"
+        )?;
+        for r in self.rows.iter() {
+            let (max_ulps, valid) = match &r.validation {
+                Some(v) => (v.max_ulps.to_string(), v.ok().to_string()),
+                None => (String::new(), String::new()),
+            };
+            writeln!(
+                f,
+                "{},{},{},{},{},{},{},{},{},{},{},{}",
+                r.program,
+                r.implementation,
+                r.mean,
+                r.stddev,
+                r.median,
+                r.min,
+                r.max,
+                r.ratio,
+                r.user,
+                r.system,
+                max_ulps,
+                valid
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, f: &mut impl Write) -> anyhow::Result<()> {
+        /// Statistics of the ratios, null if the implementation has no results.
+        #[derive(serde::Serialize)]
+        struct Summary<'a> {
+            implementation: &'a str,
+            geomean: f64,
+            harmonic: f64,
+            median: f64,
+        }
+
+        #[derive(serde::Serialize)]
+        struct Document<'a> {
+            /// What the ratios are ratios of.
+            central: String,
+            programs: &'a [String],
+            implementations: &'a [String],
+            weights: BTreeMap<&'a str, f64>,
+            results: &'a [Row],
+            summary: Vec<Summary<'a>>,
+            diagnostics: &'a [String],
+        }
+
+        let doc = Document {
+            central: self.central.describe(),
+            programs: &self.programs,
+            implementations: &self.implementations,
+            weights: self
+                .programs
+                .iter()
+                .map(|p| (p.as_str(), self.weight(p)))
+                .collect(),
+            results: &self.rows,
+            summary: self
+                .implementations
+                .iter()
+                .map(|imp| Summary {
+                    implementation: imp,
+                    geomean: self.summary(imp, Statistic::Geomean),
+                    harmonic: self.summary(imp, Statistic::Harmonic),
+                    median: self.summary(imp, Statistic::Median),
+                })
+                .collect(),
+            diagnostics: &self.diagnostics,
+        };
+        serde_json::to_writer_pretty(&mut *f, &doc)?;
+        writeln!(f)?;
+        Ok(())
+    }
+
+    fn write_markdown(&self, f: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            f,
+            "{} time relative to rust-native (lower is better).",
+            capitalize(&self.central.describe())
+        )?;
+        let weights = self.describe_weights();
+        if !weights.is_empty() {
+            writeln!(f, "Summaries weight the programs as {}.", weights)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "| Program | {} |", self.implementations.join(" | "))?;
+        writeln!(f, "|---|{}", "---:|".repeat(self.implementations.len()))?;
+        for progn in self.programs.iter() {
+            write!(f, "| {} |", progn)?;
+            for imp in self.implementations.iter() {
+                match self.get(progn, imp) {
+                    Some(r) => write!(f, " {:.2} |", r.ratio)?,
+                    None => write!(f, " - |")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        for &stat in STATISTICS.iter() {
+            write!(f, "| {} |", stat.describe())?;
+            for imp in self.implementations.iter() {
+                let s = self.summary(imp, stat);
+                if s.is_nan() {
+                    write!(f, " - |")?;
+                } else {
+                    write!(f, " {:.2} |", s)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Percentage of the mean time spent in user mode / kernel mode / idle."
+        )?;
+        writeln!(f)?;
+        writeln!(f, "| Program | {} |", self.implementations.join(" | "))?;
+        writeln!(f, "|---|{}", "---:|".repeat(self.implementations.len()))?;
+        for progn in self.programs.iter() {
+            write!(f, "| {} |", progn)?;
+            for imp in self.implementations.iter() {
+                match self.get(progn, imp) {
+                    Some(r) => {
+                        let (user, system, idle) = r.breakdown();
+                        write!(
+                            f,
+                            " {:.0}/{:.0}/{:.0} |",
+                            user * 100.0,
+                            system * 100.0,
+                            idle * 100.0
+                        )?
+                    }
+                    None => write!(f, " - |")?,
+                }
+            }
+            writeln!(f)?;
+        }
+
+        if self.rows.iter().any(|r| r.validation.is_some()) {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Output compared with the reference runtime, as the largest difference \
+                 in ulps between numbers."
+            )?;
+            writeln!(f)?;
+            writeln!(f, "| Program | {} |", self.implementations.join(" | "))?;
+            writeln!(f, "|---|{}", "---:|".repeat(self.implementations.len()))?;
+            for progn in self.programs.iter() {
+                write!(f, "| {} |", progn)?;
+                for imp in self.implementations.iter() {
+                    match self.get(progn, imp).and_then(|r| r.validation.as_ref()) {
+                        None => write!(f, " - |")?,
+                        Some(v) if v.reference == *imp => write!(f, " reference |")?,
+                        Some(v) if v.ok() => write!(f, " ok ({}) |", v.max_ulps)?,
+                        Some(_) => write!(f, " **differs** |")?,
+                    }
+                }
+                writeln!(f)?;
+            }
+        }
+
+        if !self.diagnostics.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## Diagnostics")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Files which could not be used are left out, and statistics which \
+                 disagree with the run times are replaced by the recomputed ones."
+            )?;
+            writeln!(f)?;
+            for d in self.diagnostics.iter() {
+                writeln!(f, "* {}", d)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut c = s.chars();
+    match c.next() {
+        Some(first) => first.to_uppercase().chain(c).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= 1e-12 * b.abs(), "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn central_of_times() {
+        let times = [4.0, 1.0, 3.0, 2.0, 100.0, 5.0, 6.0, 7.0];
+        assert_eq!(Central::Mean.of(&times), 16.0);
+        assert_eq!(Central::Median.of(&times), 4.5);
+        assert_eq!(Central::Min.of(&times), 1.0);
+        assert_eq!(Central::Trimmed(0.0).of(&times), 16.0);
+        assert_eq!(Central::Trimmed(0.25).of(&times), 4.5);
+        assert_eq!(Central::Trimmed(0.5).of(&times), 4.5);
+        assert_eq!(Central::Trimmed(0.5).of(&times[..7]), 4.0);
+        assert_eq!(Central::Trimmed(0.5).of(&[3.0]), 3.0);
+    }
+
+    #[test]
+    fn central_from_str() {
+        assert_eq!("trimmed".parse::<Central>().unwrap(), Central::Trimmed(0.1));
+        assert_eq!(
+            "trimmed:0".parse::<Central>().unwrap(),
+            Central::Trimmed(0.0)
+        );
+        assert!("trimmed:0.5".parse::<Central>().is_err());
+        assert!("trimmed:-0.1".parse::<Central>().is_err());
+    }
+
+    #[test]
+    fn statistic_of_ratios() {
+        let v = [(1.0, 1.0), (4.0, 1.0), (16.0, 1.0)];
+        assert_close(Statistic::Geomean.of(&v), 4.0);
+        assert_close(Statistic::Harmonic.of(&v), 3.0 / (1.0 + 0.25 + 0.0625));
+        assert_eq!(Statistic::Median.of(&v), 4.0);
+
+        let weighted = [(2.0, 3.0), (8.0, 1.0)];
+        assert_close(
+            Statistic::Geomean.of(&weighted),
+            2f64.powf(0.75) * 8f64.powf(0.25),
+        );
+        assert_close(Statistic::Harmonic.of(&weighted), 4.0 / (1.5 + 0.125));
+        assert_eq!(Statistic::Median.of(&weighted), 2.0);
+
+        for s in STATISTICS {
+            assert!(s.of(&[]).is_nan());
+            assert!(s.of(&[(1.0, 0.0)]).is_nan());
+        }
+    }
+
+    #[test]
+    fn weighted_median() {
+        // the lower one for an even count
+        assert_eq!(Statistic::Median.of(&[(3.0, 1.0), (1.0, 1.0)]), 1.0);
+        assert_eq!(
+            Statistic::Median.of(&[(3.0, 1.0), (1.0, 1.0), (2.0, 1.0), (4.0, 1.0)]),
+            2.0
+        );
+        // ties
+        assert_eq!(
+            Statistic::Median.of(&[(2.0, 1.0), (1.0, 1.0), (2.0, 1.0)]),
+            2.0
+        );
+        assert_eq!(
+            Statistic::Median.of(&[(1.0, 1.0), (1.0, 1.0), (2.0, 1.0)]),
+            1.0
+        );
+        // weights move it
+        assert_eq!(
+            Statistic::Median.of(&[(1.0, 1.0), (2.0, 1.0), (3.0, 5.0)]),
+            3.0
+        );
+        assert_eq!(
+            Statistic::Median.of(&[(1.0, 0.5), (2.0, 0.5), (3.0, 1.0)]),
+            2.0
+        );
+    }
+
+    fn report(central: Central, native: &[f64], wasmtime: &[f64]) -> Report {
+        let row = |implementation: &str, times: &[f64]| Row {
+            program: "nbody".to_string(),
+            implementation: implementation.to_string(),
+            mean: hyperfine::mean(times),
+            stddev: hyperfine::stddev(times),
+            median: hyperfine::median(times),
+            min: Central::Min.of(times),
+            max: times.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            ratio: central.of(times) / central.of(native),
+            user: 0.0,
+            system: 0.0,
+            validation: None,
+        };
+        Report {
+            rows: vec![row("rust-native", native), row("rust-wasmtime", wasmtime)],
+            central,
+            ..Report::default()
+        }
+    }
+
+    #[test]
+    fn outliers_depend_on_the_central_statistic() {
+        // one run of wasmtime was disturbed
+        let native = [1.0; 10];
+        let mut wasmtime = [1.5; 10];
+        wasmtime[3] = 20.0;
+
+        let mean = report(Central::Mean, &native, &wasmtime);
+        assert_close(mean.summary("rust-wasmtime", Statistic::Geomean), 3.35);
+        assert!(mean.is_outlier("rust-wasmtime", Statistic::Geomean, 2.0));
+        assert!(!mean.is_outlier("rust-wasmtime", Statistic::Geomean, 4.0));
+        assert!(!mean.is_outlier("rust-native", Statistic::Geomean, 1.0));
+
+        for central in [Central::Median, Central::Trimmed(0.1)] {
+            let r = report(central, &native, &wasmtime);
+            assert_eq!(r.summary("rust-wasmtime", Statistic::Geomean), 1.5);
+            assert!(!r.is_outlier("rust-wasmtime", Statistic::Geomean, 2.0));
+            assert!(!r.is_outlier("rust-wasmtime", Statistic::Geomean, 1.5));
+            assert!(r.is_outlier("rust-wasmtime", Statistic::Geomean, 1.4));
+        }
+
+        // no results, no outlier
+        assert!(!mean.is_outlier("rust-wasm3", Statistic::Geomean, 0.0));
+    }
+
+    #[test]
+    fn zero_weights_are_left_out() {
+        let v = [(0.0, 0.0), (1.0, 0.0), (2.0, 1.0), (8.0, 1.0), (100.0, 0.0)];
+        assert_close(Statistic::Geomean.of(&v), 4.0);
+        assert_close(Statistic::Harmonic.of(&v), 2.0 / (0.5 + 0.125));
+        assert_eq!(Statistic::Median.of(&v), 2.0);
+    }
+}
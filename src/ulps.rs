@@ -0,0 +1,167 @@
+//! Comparing the output of a program on two runtimes, allowing numbers to
+//! differ by a few units in the last place (ulps), e.g. because one wasm
+//! compiler contracts `a * b + c` into a fused multiply-add.
+
+use std::path::Path;
+
+/// Verdict on the output of a runtime, `results/<name>.validation.json`.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Validation {
+    /// Runtime whose output is the reference.
+    pub reference: String,
+    /// Largest difference in ulps allowed between numbers.
+    pub tolerance: u64,
+    /// Largest difference in ulps between numbers found. Words which are not
+    /// numbers must be equal.
+    pub max_ulps: u64,
+    /// The first difference beyond the tolerance.
+    pub mismatch: Option<String>,
+}
+
+impl Validation {
+    pub fn ok(&self) -> bool {
+        self.mismatch.is_none()
+    }
+
+    /// Read `path`, `None` if it does not exist.
+    pub fn load(path: &str) -> anyhow::Result<Option<Validation>> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_reader(std::fs::File::open(path)?)?))
+    }
+}
+
+/// Number of representable doubles between `a` and `b`. Two NaNs are equal
+/// whatever their payload, a NaN and a number are as far apart as possible.
+pub fn distance(a: f64, b: f64) -> u64 {
+    if a.is_nan() || b.is_nan() {
+        return if a.is_nan() && b.is_nan() {
+            0
+        } else {
+            u64::MAX
+        };
+    }
+    // map the sign-magnitude bits onto a monotonic integer line
+    let key = |x: f64| {
+        let bits = x.to_bits() as i64;
+        if bits < 0 {
+            i64::MIN - bits
+        } else {
+            bits
+        }
+    };
+    (key(a) as i128 - key(b) as i128).unsigned_abs() as u64
+}
+
+/// Compare `output` with `reference` word by word, returning the largest
+/// difference in ulps and the first difference beyond `tolerance`.
+pub fn compare(reference: &str, output: &str, tolerance: u64) -> (u64, Option<String>) {
+    let mut max_ulps = 0;
+    let mut mismatch = None;
+
+    for (i, (r, o)) in reference.lines().zip(output.lines()).enumerate() {
+        let (rw, ow) = (r.split_whitespace(), o.split_whitespace());
+        if rw.clone().count() != ow.clone().count() {
+            return (
+                u64::MAX,
+                Some(format!("line {}: `{}` instead of `{}`", i + 1, o, r)),
+            );
+        }
+        for (r, o) in rw.zip(ow) {
+            let d = match (r.parse::<f64>(), o.parse::<f64>()) {
+                (Ok(r), Ok(o)) => distance(r, o),
+                _ if r == o => 0,
+                _ => u64::MAX,
+            };
+            max_ulps = max_ulps.max(d);
+            if d > tolerance && mismatch.is_none() {
+                mismatch = Some(format!("line {}: {} instead of {}", i + 1, o, r));
+            }
+        }
+    }
+
+    let (rn, on) = (reference.lines().count(), output.lines().count());
+    if rn != on {
+        return (u64::MAX, Some(format!("{} lines instead of {}", on, rn)));
+    }
+    (max_ulps, mismatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_counts_doubles_in_between() {
+        assert_eq!(distance(1.0, 1.0), 0);
+        assert_eq!(distance(1.0, f64::from_bits(1.0f64.to_bits() + 3)), 3);
+        assert_eq!(distance(f64::from_bits(1.0f64.to_bits() + 3), 1.0), 3);
+        assert_eq!(distance(-1.0, f64::from_bits((-1.0f64).to_bits() + 2)), 2);
+    }
+
+    #[test]
+    fn distance_crosses_zero() {
+        let tiny = f64::from_bits(1);
+        assert_eq!(distance(0.0, -0.0), 0);
+        assert_eq!(distance(tiny, 0.0), 1);
+        assert_eq!(distance(tiny, -0.0), 1);
+        assert_eq!(distance(-tiny, tiny), 2);
+        assert_eq!(distance(-f64::MAX, f64::MAX), 2 * f64::MAX.to_bits());
+    }
+
+    #[test]
+    fn distance_of_nan() {
+        let other_nan = f64::from_bits(f64::NAN.to_bits() | 1);
+        assert_eq!(distance(f64::NAN, other_nan), 0);
+        assert_eq!(distance(f64::NAN, 1.0), u64::MAX);
+        assert_eq!(distance(0.0, -f64::NAN), u64::MAX);
+    }
+
+    #[test]
+    fn compare_within_tolerance() {
+        let a = 0.1f64;
+        let b = f64::from_bits(a.to_bits() + 2);
+        let reference = format!("energy {:e}\n{:e}\n", a, a);
+        let output = format!("energy {:e}\n{:e}\n", b, a);
+        assert_eq!(compare(&reference, &output, 2), (2, None));
+
+        let (max_ulps, mismatch) = compare(&reference, &output, 1);
+        assert_eq!(max_ulps, 2);
+        assert_eq!(
+            mismatch.as_deref(),
+            Some(&*format!("line 1: {:e} instead of {:e}", b, a))
+        );
+    }
+
+    #[test]
+    fn compare_words() {
+        assert_eq!(compare("ok 1\n", "ok 1\n", 0), (0, None));
+
+        let (max_ulps, mismatch) = compare("ok 1\n", "ko 1\n", 4);
+        assert_eq!(max_ulps, u64::MAX);
+        assert_eq!(mismatch.as_deref(), Some("line 1: ko instead of ok"));
+
+        let (max_ulps, mismatch) = compare("1\n", "one\n", 4);
+        assert_eq!(max_ulps, u64::MAX);
+        assert_eq!(mismatch.as_deref(), Some("line 1: one instead of 1"));
+    }
+
+    #[test]
+    fn compare_counts() {
+        let (max_ulps, mismatch) = compare("1 2\n", "1 2 3\n", 4);
+        assert_eq!(max_ulps, u64::MAX);
+        assert_eq!(
+            mismatch.as_deref(),
+            Some("line 1: `1 2 3` instead of `1 2`")
+        );
+
+        let (max_ulps, mismatch) = compare("1\n2\n", "1\n", 4);
+        assert_eq!(max_ulps, u64::MAX);
+        assert_eq!(mismatch.as_deref(), Some("1 lines instead of 2"));
+
+        let (max_ulps, mismatch) = compare("1\n", "1\n2\n", 4);
+        assert_eq!(max_ulps, u64::MAX);
+        assert_eq!(mismatch.as_deref(), Some("2 lines instead of 1"));
+    }
+}